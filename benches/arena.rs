@@ -0,0 +1,147 @@
+//! Compares the arena layout against the boxed one.
+//!
+//! Run with `cargo bench --bench arena`.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use treap::{arena::ArenaTreapMap, map::TreapMap};
+
+const SIZES: [u32; 3] = [1_000, 100_000, 1_000_000];
+
+fn shuffled(n: u32) -> Vec<u32> {
+    let mut keys: Vec<_> = (0..n).collect();
+    keys.shuffle(&mut StdRng::seed_from_u64(n.into()));
+    keys
+}
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    group.sample_size(10);
+
+    for n in SIZES {
+        let keys = shuffled(n);
+
+        group.bench_with_input(BenchmarkId::new("box", n), &keys, |b, keys| {
+            b.iter(|| {
+                let mut map = TreapMap::with_seed(1);
+                for &key in keys {
+                    map.insert(key, key);
+                }
+                map
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("arena", n), &keys, |b, keys| {
+            b.iter(|| {
+                let mut map = ArenaTreapMap::with_seed(1);
+                for &key in keys {
+                    map.insert(key, key);
+                }
+                map
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("arena_reserved", n), &keys, |b, keys| {
+            b.iter(|| {
+                let mut map = ArenaTreapMap::with_seed(1);
+                map.reserve(keys.len());
+                for &key in keys {
+                    map.insert(key, key);
+                }
+                map
+            })
+        });
+    }
+}
+
+fn get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+
+    for n in SIZES {
+        let keys = shuffled(n);
+        let boxed: TreapMap<_, _> = keys.iter().map(|&key| (key, key)).collect();
+        let arena: ArenaTreapMap<_, _> = keys.iter().map(|&key| (key, key)).collect();
+        let probes = &keys[..keys.len().min(10_000)];
+
+        group.bench_with_input(BenchmarkId::new("box", n), probes, |b, probes| {
+            b.iter(|| {
+                probes
+                    .iter()
+                    .filter(|&key| boxed.get(key).is_some())
+                    .count()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("arena", n), probes, |b, probes| {
+            b.iter(|| {
+                probes
+                    .iter()
+                    .filter(|&key| arena.get(key).is_some())
+                    .count()
+            })
+        });
+    }
+}
+
+fn iter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("iter");
+
+    for n in SIZES {
+        let keys = shuffled(n);
+        let boxed: TreapMap<_, _> = keys.iter().map(|&key| (key, key)).collect();
+        let arena: ArenaTreapMap<_, _> = keys.iter().map(|&key| (key, key)).collect();
+
+        group.bench_function(BenchmarkId::new("box", n), |b| {
+            b.iter(|| boxed.values().copied().map(u64::from).sum::<u64>())
+        });
+        group.bench_function(BenchmarkId::new("arena", n), |b| {
+            b.iter(|| arena.iter().map(|(_, &v)| u64::from(v)).sum::<u64>())
+        });
+    }
+}
+
+fn churn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("churn");
+
+    for n in [1_000, 100_000] {
+        let keys = shuffled(n);
+        let boxed: TreapMap<_, _> = keys.iter().map(|&key| (key, key)).collect();
+        let arena: ArenaTreapMap<_, _> = keys.iter().map(|&key| (key, key)).collect();
+        let probes = &keys[..keys.len().min(1_000)];
+
+        // remove a key and put it back, so the arena reuses its free slot
+        group.bench_function(BenchmarkId::new("box", n), |b| {
+            b.iter_batched_ref(
+                || boxed_clone(&boxed),
+                |map| {
+                    for &key in probes {
+                        let val = map.remove(&key).unwrap();
+                        map.insert(black_box(key), val);
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function(BenchmarkId::new("arena", n), |b| {
+            b.iter_batched_ref(
+                || {
+                    arena
+                        .iter()
+                        .map(|(&k, &v)| (k, v))
+                        .collect::<ArenaTreapMap<_, _>>()
+                },
+                |map| {
+                    for &key in probes {
+                        let val = map.remove(&key).unwrap();
+                        map.insert(black_box(key), val);
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+}
+
+fn boxed_clone(map: &TreapMap<u32, u32>) -> TreapMap<u32, u32> {
+    map.iter().map(|(&k, &v)| (k, v)).collect()
+}
+
+criterion_group!(benches, insert, get, iter, churn);
+criterion_main!(benches);
@@ -0,0 +1,925 @@
+//! An ordered map storing its nodes in a single vector, with stable
+//! [`Handle`]s to its entries.
+//!
+//! The boxed [`TreapMap`](crate::map::TreapMap) rotates by swapping entries
+//! between nodes, so its [handles](crate::map::Handle) keep a copy of the
+//! key and look the entry up again in O(log n). Here entries never leave
+//! their slot, and a handle is a `Copy` index that resolves in O(1).
+
+use std::{borrow::Borrow, cmp::Ordering, fmt, iter::FusedIterator, mem, ops::RangeBounds};
+
+use rand::RngCore;
+
+use crate::{
+    map::check_range,
+    node::{Side, Split, Walk},
+    priority::{DefaultPriority, PrioritySource},
+};
+
+/// The index standing for a missing child.
+const NIL: u32 = u32::MAX;
+
+struct Node<K, V> {
+    key: K,
+    val: V,
+    priority: u64,
+    size: u32,
+    /// Tells this entry apart from the others that held the same slot.
+    generation: u32,
+    left: u32,
+    right: u32,
+}
+
+enum Slot<K, V> {
+    Occupied(Node<K, V>),
+    /// A free slot, linked to the next one on the free list.
+    Vacant(u32),
+}
+
+/// An ordered map whose nodes live side by side in a single [`Vec`].
+///
+/// Children are `u32` indices into that vector rather than boxes, so nodes
+/// are smaller, neighbouring nodes tend to share cache lines, and building
+/// and dropping the map takes a handful of allocations instead of one per
+/// entry. Slots freed by removals are kept on a free list and reused by the
+/// next insertions.
+///
+/// A node never moves to another slot: rotations relink indices and leave
+/// the entries where they are. This is what makes a [`Handle`] possible.
+///
+/// ```
+/// use treap::arena::ArenaTreapMap;
+///
+/// let mut map = ArenaTreapMap::with_capacity(2);
+/// map.insert(1, "a");
+/// map.insert(2, "b");
+///
+/// assert_eq!(map.get(&1), Some(&"a"));
+/// assert!(map.capacity() >= 2);
+/// ```
+pub struct ArenaTreapMap<K, V, P = DefaultPriority> {
+    slots: Vec<Slot<K, V>>,
+    root: u32,
+    free: u32,
+    len: usize,
+    /// The generation of the next entry to be allocated.
+    generation: u32,
+    priorities: P,
+}
+
+/// Points at an entry of an [`ArenaTreapMap`] without borrowing the map.
+///
+/// A handle stays valid for as long as its entry is in the map, whatever
+/// else gets inserted or removed and however the tree is rotated. Once the
+/// entry is removed the handle goes stale, and the `*_by_handle` methods
+/// return `None` for it, even after its slot is reused by another entry.
+///
+/// Stale handles are recognized by a 32-bit generation, so a handle kept
+/// across more than four billion insertions may be mistaken for a live one.
+///
+/// ```
+/// use treap::arena::ArenaTreapMap;
+///
+/// let mut map = ArenaTreapMap::new();
+/// let (a, _) = map.insert_with_handle("a", 1);
+/// map.insert("b", 2);
+///
+/// *map.get_by_handle_mut(a).unwrap().1 += 10;
+/// assert_eq!(map.get(&"a"), Some(&11));
+///
+/// assert_eq!(map.remove_by_handle(a), Some(("a", 11)));
+/// assert_eq!(map.get_by_handle(a), None);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+impl<K, V> ArenaTreapMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_priorities(DefaultPriority::new())
+    }
+
+    /// Creates a map whose priorities are drawn from a generator seeded
+    /// with `seed`, so that the same operations always build the same tree.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_priorities(DefaultPriority::with_seed(seed))
+    }
+
+    /// Creates a map with room for at least `capacity` entries before it
+    /// has to reallocate.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut map = Self::new();
+        map.reserve(capacity);
+
+        map
+    }
+}
+
+impl<K, V, R: RngCore> ArenaTreapMap<K, V, R> {
+    /// Creates a map whose priorities are drawn from `rng`.
+    pub fn with_rng(rng: R) -> Self {
+        Self::with_priorities(rng)
+    }
+}
+
+impl<K, V, P> ArenaTreapMap<K, V, P> {
+    pub fn with_priorities(priorities: P) -> Self {
+        Self {
+            slots: Vec::new(),
+            root: NIL,
+            free: NIL,
+            len: 0,
+            generation: 0,
+            priorities,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns how many entries the map can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    /// Reserves room for at least `additional` more entries, counting the
+    /// free slots left behind by removals.
+    pub fn reserve(&mut self, additional: usize) {
+        let free = self.slots.len() - self.len;
+        self.slots.reserve(additional.saturating_sub(free));
+    }
+
+    /// Releases the free slots at the end of the arena and shrinks its
+    /// allocation to fit.
+    ///
+    /// Free slots between occupied ones are kept, since nodes never move.
+    pub fn shrink_to_fit(&mut self) {
+        while let Some(Slot::Vacant(_)) = self.slots.last() {
+            self.slots.pop();
+        }
+
+        // the free list may have pointed past the new end
+        self.free = NIL;
+        for (i, slot) in self.slots.iter_mut().enumerate().rev() {
+            if let Slot::Vacant(next) = slot {
+                *next = self.free;
+                self.free = i as u32;
+            }
+        }
+
+        self.slots.shrink_to_fit();
+    }
+
+    /// Removes every entry, keeping the allocation.
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.root = NIL;
+        self.free = NIL;
+        self.len = 0;
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.search(key).map(|at| &self.node(at).val)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.search(key).map(|at| &mut self.node_mut(at).val)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.search(key).is_some()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the map would hold more than `u32::MAX - 1` entries.
+    pub fn insert(&mut self, key: K, val: V) -> Option<V>
+    where
+        K: Ord,
+        P: PrioritySource,
+    {
+        self.insert_with_handle(key, val).1
+    }
+
+    /// Like [`ArenaTreapMap::insert`], also returning a handle to the entry.
+    ///
+    /// If the key was already present, the entry keeps its handle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the map would hold more than `u32::MAX - 1` entries.
+    pub fn insert_with_handle(&mut self, key: K, val: V) -> (Handle, Option<V>)
+    where
+        K: Ord,
+        P: PrioritySource,
+    {
+        let priority = self.priorities.next_priority();
+        let (mut path, found) = self.search_path(&key);
+
+        if let Some(at) = found {
+            // like the boxed map, keep the key and the higher priority
+            let node = self.node_mut(at);
+            let old_val = mem::replace(&mut node.val, val);
+            if node.priority < priority {
+                node.priority = priority;
+                self.sift_up(&mut path, at);
+            }
+
+            return (self.handle_at(at), Some(old_val));
+        }
+
+        let at = self.alloc(Node {
+            key,
+            val,
+            priority,
+            size: 1,
+            generation: self.generation,
+            left: NIL,
+            right: NIL,
+        });
+        self.generation = self.generation.wrapping_add(1);
+        self.relink(path.last().copied(), at);
+        for &(ancestor, _) in &path {
+            self.node_mut(ancestor).size += 1;
+        }
+        self.sift_up(&mut path, at);
+        self.len += 1;
+
+        (self.handle_at(at), None)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.remove_entry(key).map(|(_, val)| val)
+    }
+
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let (path, found) = self.search_path(key);
+
+        Some(self.unlink(path, found?))
+    }
+
+    /// Returns a handle to the entry for `key`.
+    pub fn handle<Q>(&self, key: &Q) -> Option<Handle>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.search(key).map(|at| self.handle_at(at))
+    }
+
+    /// Returns the entry behind `handle`, or `None` if it was removed.
+    pub fn get_by_handle(&self, handle: Handle) -> Option<(&K, &V)> {
+        self.resolve(handle)
+            .map(|at| self.node(at))
+            .map(|node| (&node.key, &node.val))
+    }
+
+    /// Like [`ArenaTreapMap::get_by_handle`], with a mutable reference to
+    /// the value.
+    pub fn get_by_handle_mut(&mut self, handle: Handle) -> Option<(&K, &mut V)> {
+        self.resolve(handle)
+            .map(|at| self.node_mut(at))
+            .map(|node| (&node.key, &mut node.val))
+    }
+
+    /// Removes the entry behind `handle`, or returns `None` if it was already
+    /// removed.
+    ///
+    /// Takes O(log n): nodes don't know their parents, so the path down to
+    /// the entry is found again from its key.
+    pub fn remove_by_handle(&mut self, handle: Handle) -> Option<(K, V)>
+    where
+        K: Ord,
+    {
+        let at = self.resolve(handle)?;
+        let (path, found) = self.search_path(&self.node(at).key);
+        debug_assert_eq!(found, Some(at));
+
+        Some(self.unlink(path, at))
+    }
+
+    /// Returns whether the entry behind `handle` is still in the map.
+    pub fn contains_handle(&self, handle: Handle) -> bool {
+        self.resolve(handle).is_some()
+    }
+
+    fn handle_at(&self, at: u32) -> Handle {
+        Handle {
+            index: at,
+            generation: self.node(at).generation,
+        }
+    }
+
+    /// Returns the slot behind `handle`, if it still holds the same entry.
+    fn resolve(&self, handle: Handle) -> Option<u32> {
+        match self.slots.get(handle.index as usize) {
+            Some(Slot::Occupied(node)) if node.generation == handle.generation => {
+                Some(handle.index)
+            }
+            _ => None,
+        }
+    }
+
+    /// Removes the node at `at`, below the nodes on `path`.
+    fn unlink(&mut self, mut path: Vec<(u32, Side)>, at: u32) -> (K, V) {
+        // rotate the higher priority child above us until we have at most
+        // one child, which then takes our place
+        loop {
+            let Node { left, right, .. } = *self.node(at);
+            let side = match (left, right) {
+                (NIL, _) | (_, NIL) => break,
+                _ if self.node(left).priority > self.node(right).priority => Side::Left,
+                _ => Side::Right,
+            };
+
+            let child = self.rotate_up(path.last().copied(), at, side);
+            path.push((child, side.opposite()));
+        }
+
+        let Node { left, right, .. } = *self.node(at);
+        self.relink(path.last().copied(), if left == NIL { right } else { left });
+        for &(ancestor, _) in path.iter().rev() {
+            self.update(ancestor);
+        }
+        self.len -= 1;
+
+        self.dealloc(at)
+    }
+
+    /// Iterates over the entries of the map, sorted by key.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            walk: Walk::new(self.node_ref(self.root)),
+            len: self.len,
+        }
+    }
+
+    /// Iterates over the entries whose keys fall within `range`, sorted by
+    /// key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end, or if both
+    /// ends are equal and excluded.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        check_range(&range);
+
+        Range {
+            walk: Walk::range(self.node_ref(self.root), &range),
+        }
+    }
+
+    fn node(&self, at: u32) -> &Node<K, V> {
+        match &self.slots[at as usize] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant(_) => free_slot(),
+        }
+    }
+
+    fn node_mut(&mut self, at: u32) -> &mut Node<K, V> {
+        match &mut self.slots[at as usize] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant(_) => free_slot(),
+        }
+    }
+
+    fn node_ref(&self, at: u32) -> Option<NodeRef<'_, K, V>> {
+        (at != NIL).then_some(NodeRef {
+            slots: &self.slots,
+            at,
+        })
+    }
+
+    fn child(&self, at: u32, side: Side) -> u32 {
+        let node = self.node(at);
+        match side {
+            Side::Left => node.left,
+            Side::Right => node.right,
+        }
+    }
+
+    fn child_mut(&mut self, at: u32, side: Side) -> &mut u32 {
+        let node = self.node_mut(at);
+        match side {
+            Side::Left => &mut node.left,
+            Side::Right => &mut node.right,
+        }
+    }
+
+    fn size(&self, at: u32) -> u32 {
+        if at == NIL {
+            0
+        } else {
+            self.node(at).size
+        }
+    }
+
+    fn update(&mut self, at: u32) {
+        let Node { left, right, .. } = *self.node(at);
+        self.node_mut(at).size = 1 + self.size(left) + self.size(right);
+    }
+
+    fn search<Q>(&self, key: &Q) -> Option<u32>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let mut at = self.root;
+
+        // `NIL` is past the end of any arena, so it fails the bounds check
+        while let Some(Slot::Occupied(node)) = self.slots.get(at as usize) {
+            at = match key.cmp(node.key.borrow()) {
+                Ordering::Equal => return Some(at),
+                Ordering::Less => node.left,
+                Ordering::Greater => node.right,
+            };
+        }
+
+        None
+    }
+
+    /// Looks for `key` and records every node passed on the way down, with
+    /// the side the search went on.
+    fn search_path<Q>(&self, key: &Q) -> (Vec<(u32, Side)>, Option<u32>)
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let mut path = Vec::new();
+        let mut at = self.root;
+
+        while at != NIL {
+            let side = match key.cmp(self.node(at).key.borrow()) {
+                Ordering::Equal => return (path, Some(at)),
+                Ordering::Less => Side::Left,
+                Ordering::Greater => Side::Right,
+            };
+
+            path.push((at, side));
+            at = self.child(at, side);
+        }
+
+        (path, None)
+    }
+
+    /// Points the link below `parent`, or the root if there is none, at
+    /// `child`.
+    fn relink(&mut self, parent: Option<(u32, Side)>, child: u32) {
+        match parent {
+            Some((parent, side)) => *self.child_mut(parent, side) = child,
+            None => self.root = child,
+        }
+    }
+
+    /// Rotates the child on `side` of `at` above it, and returns that child.
+    fn rotate_up(&mut self, parent: Option<(u32, Side)>, at: u32, side: Side) -> u32 {
+        let child = self.child(at, side);
+        let inner = self.child(child, side.opposite());
+
+        *self.child_mut(at, side) = inner;
+        *self.child_mut(child, side.opposite()) = at;
+        self.relink(parent, child);
+
+        self.update(at);
+        self.update(child);
+
+        child
+    }
+
+    /// Rotates `at` up past its ancestors on `path` until the heap invariant
+    /// holds again.
+    fn sift_up(&mut self, path: &mut Vec<(u32, Side)>, at: u32) {
+        while let Some(&(parent, side)) = path.last() {
+            if self.node(parent).priority >= self.node(at).priority {
+                break;
+            }
+
+            path.pop();
+            self.rotate_up(path.last().copied(), parent, side);
+        }
+    }
+
+    fn alloc(&mut self, node: Node<K, V>) -> u32 {
+        if self.free != NIL {
+            let at = self.free;
+            match mem::replace(&mut self.slots[at as usize], Slot::Occupied(node)) {
+                Slot::Vacant(next) => self.free = next,
+                Slot::Occupied(_) => unreachable!("occupied slot on the free list"),
+            }
+
+            return at;
+        }
+
+        let at = self.slots.len();
+        assert!(at < NIL as usize, "ArenaTreapMap is full");
+        self.slots.push(Slot::Occupied(node));
+
+        at as u32
+    }
+
+    fn dealloc(&mut self, at: u32) -> (K, V) {
+        match mem::replace(&mut self.slots[at as usize], Slot::Vacant(self.free)) {
+            Slot::Occupied(node) => {
+                self.free = at;
+                (node.key, node.val)
+            }
+            Slot::Vacant(_) => unreachable!("freeing a free slot"),
+        }
+    }
+}
+
+impl<K, V, P: Default> Default for ArenaTreapMap<K, V, P> {
+    fn default() -> Self {
+        Self::with_priorities(P::default())
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, P> fmt::Debug for ArenaTreapMap<K, V, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Ord, V, P: PrioritySource + Default> FromIterator<(K, V)> for ArenaTreapMap<K, V, P> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+
+        map
+    }
+}
+
+impl<K: Ord, V, P: PrioritySource> Extend<(K, V)> for ArenaTreapMap<K, V, P> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+
+        for (key, val) in iter {
+            self.insert(key, val);
+        }
+    }
+}
+
+impl<'a, K, V, P> IntoIterator for &'a ArenaTreapMap<K, V, P> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cold]
+fn free_slot() -> ! {
+    unreachable!("link to a free slot")
+}
+
+/// A node in the arena, for walking the tree.
+struct NodeRef<'a, K, V> {
+    slots: &'a [Slot<K, V>],
+    at: u32,
+}
+
+impl<'a, K, V> NodeRef<'a, K, V> {
+    fn node(&self) -> &'a Node<K, V> {
+        match &self.slots[self.at as usize] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant(_) => free_slot(),
+        }
+    }
+
+    fn to(&self, at: u32) -> Option<Self> {
+        (at != NIL).then_some(Self {
+            slots: self.slots,
+            at,
+        })
+    }
+}
+
+impl<K, V> Clone for NodeRef<'_, K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V> Copy for NodeRef<'_, K, V> {}
+
+impl<'a, K, V> Split for NodeRef<'a, K, V> {
+    type Key = K;
+    type Entry = (&'a K, &'a V);
+
+    fn key(&self) -> &K {
+        &self.node().key
+    }
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        let node = self.node();
+
+        (
+            self.to(node.left),
+            (&node.key, &node.val),
+            self.to(node.right),
+        )
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    walk: Walk<NodeRef<'a, K, V>>,
+    len: usize,
+}
+
+impl<K, V> Clone for Iter<'_, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            walk: self.walk.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.walk.next_front()?;
+        self.len -= 1;
+
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let entry = self.walk.next_back()?;
+        self.len -= 1;
+
+        Some(entry)
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+impl<K, V> FusedIterator for Iter<'_, K, V> {}
+
+pub struct Range<'a, K, V> {
+    walk: Walk<NodeRef<'a, K, V>>,
+}
+
+impl<K, V> Clone for Range<'_, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            walk: self.walk.clone(),
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walk.next_front()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Range<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.walk.next_back()
+    }
+}
+
+impl<K, V> FusedIterator for Range<'_, K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn check<K: Ord, V, P>(map: &ArenaTreapMap<K, V, P>) {
+        fn walk<'a, K: Ord, V, P>(
+            map: &'a ArenaTreapMap<K, V, P>,
+            at: u32,
+            bound: u64,
+            keys: &mut Vec<&'a K>,
+        ) -> u32 {
+            if at == NIL {
+                return 0;
+            }
+
+            let node = map.node(at);
+            assert!(node.priority <= bound);
+            let left = walk(map, node.left, node.priority, keys);
+            keys.push(&node.key);
+            let right = walk(map, node.right, node.priority, keys);
+            assert_eq!(node.size, 1 + left + right);
+
+            node.size
+        }
+
+        let mut keys = Vec::new();
+        assert_eq!(walk(map, map.root, u64::MAX, &mut keys) as usize, map.len());
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn matches_btree_map() {
+        let mut rng = StdRng::seed_from_u64(19);
+        let mut map = ArenaTreapMap::with_seed(19);
+        let mut expected = BTreeMap::new();
+
+        for i in 0..5000 {
+            let key = rng.gen_range(0..800);
+
+            if rng.gen_bool(0.4) {
+                assert_eq!(map.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(map.insert(key, i), expected.insert(key, i));
+            }
+        }
+
+        check(&map);
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter().eq(expected.iter()));
+        assert!(map.iter().rev().eq(expected.iter().rev()));
+        assert!(map.range(100..200).eq(expected.range(100..200)));
+
+        *map.get_mut(expected.keys().next().unwrap()).unwrap() = -1;
+        assert_eq!(map.iter().next().map(|(_, v)| *v), Some(-1));
+    }
+
+    #[test]
+    fn reuses_free_slots() {
+        let mut map = ArenaTreapMap::with_capacity(100);
+        let capacity = map.capacity();
+        assert!(capacity >= 100);
+
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        for i in 0..50 {
+            map.remove(&(i * 2));
+        }
+        assert_eq!(map.capacity(), capacity);
+
+        // the holes are filled before the arena grows
+        for i in 100..150 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.slots.len(), 100);
+        assert_eq!(map.capacity(), capacity);
+        check(&map);
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut map = ArenaTreapMap::new();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        for i in (0..1000).filter(|i| i % 10 == 0 || *i >= 500) {
+            map.remove(&i);
+        }
+
+        map.shrink_to_fit();
+        assert!(map.slots.len() <= 500);
+        assert!(map.capacity() <= 500);
+
+        map.reserve(1000);
+        assert!(map.capacity() >= 1450);
+        for i in 1000..2000 {
+            map.insert(i, i);
+        }
+        check(&map);
+        assert_eq!(map.len(), 1450);
+        assert_eq!(map.get(&1500), Some(&1500));
+        assert_eq!(map.get(&10), None);
+    }
+
+    #[test]
+    fn handles_survive_other_writes() {
+        let mut rng = StdRng::seed_from_u64(20);
+        let mut map = ArenaTreapMap::with_seed(20);
+        let mut handles = BTreeMap::new();
+
+        for i in 0..5000 {
+            let key = rng.gen_range(0..500);
+
+            if rng.gen_bool(0.4) {
+                let removed = map.remove(&key);
+                let handle = handles.remove(&key);
+                assert_eq!(removed.is_some(), handle.is_some());
+                if let Some(handle) = handle {
+                    assert!(!map.contains_handle(handle));
+                }
+            } else {
+                let (handle, old) = map.insert_with_handle(key, i);
+                if old.is_some() {
+                    assert_eq!(handles.get(&key), Some(&handle));
+                }
+                handles.insert(key, handle);
+            }
+        }
+
+        for (key, &handle) in &handles {
+            assert_eq!(map.handle(key), Some(handle));
+            assert_eq!(map.get_by_handle(handle).map(|(k, _)| k), Some(key));
+        }
+
+        for (key, handle) in handles {
+            *map.get_by_handle_mut(handle).unwrap().1 = -1;
+            assert_eq!(map.remove_by_handle(handle), Some((key, -1)));
+            assert_eq!(map.remove_by_handle(handle), None);
+            check(&map);
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn stale_handles() {
+        let mut map = ArenaTreapMap::new();
+        map.insert(1, 'a');
+        let a = map.handle(&1).unwrap();
+        map.remove(&1);
+
+        // the new entry reuses the slot, but not the generation
+        let (b, _) = map.insert_with_handle(2, 'b');
+        assert_eq!(a.index, b.index);
+        assert_eq!(map.get_by_handle(a), None);
+        assert_eq!(map.get_by_handle_mut(a), None);
+        assert_eq!(map.remove_by_handle(a), None);
+        assert_eq!(map.get_by_handle(b), Some((&2, &'b')));
+
+        // and neither does a slot given back by `shrink_to_fit` or `clear`
+        map.remove(&2);
+        map.shrink_to_fit();
+        let (c, _) = map.insert_with_handle(3, 'c');
+        assert!(!map.contains_handle(b));
+        map.clear();
+        let (d, _) = map.insert_with_handle(4, 'd');
+        assert_eq!(c.index, d.index);
+        assert!(!map.contains_handle(c));
+        assert!(map.contains_handle(d));
+    }
+
+    #[test]
+    fn degenerate_left_spine() {
+        struct Ascending(u64);
+
+        impl PrioritySource for Ascending {
+            fn next_priority(&mut self) -> u64 {
+                self.0 += 1;
+                self.0
+            }
+        }
+
+        const N: u32 = if cfg!(miri) { 2_000 } else { 1_000_000 };
+
+        let mut map = ArenaTreapMap::with_priorities(Ascending(0));
+        for i in 0..N {
+            map.insert(i, i);
+        }
+
+        assert_eq!(map.len(), N as usize);
+        assert_eq!(map.get(&0), Some(&0));
+        assert_eq!(map.remove(&1), Some(1));
+        assert_eq!(map.iter().nth(1), Some((&2, &2)));
+    }
+}
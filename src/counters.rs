@@ -0,0 +1,137 @@
+//! Counts of the work done inside the boxed treaps on the current thread,
+//! for checking in production that balancing behaves as expected.
+//!
+//! Counting is only compiled in with the `counters` feature. Without it
+//! this module is empty and the hooks below cost nothing.
+
+#[cfg(feature = "counters")]
+use std::cell::Cell;
+
+#[cfg(feature = "counters")]
+thread_local! {
+    static COUNTERS: Cell<Counters> = const {
+        Cell::new(Counters {
+            rotations: 0,
+            comparisons: 0,
+            allocations: 0,
+        })
+    };
+}
+
+/// The counts since the thread started or since its last [`reset`].
+///
+/// Every thread counts the work it does itself, whichever maps it is done
+/// on, so the counts don't pick up what other threads do meanwhile.
+#[cfg(feature = "counters")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// Single left or right rotations.
+    pub rotations: u64,
+    /// Key comparisons made while looking for where to insert or remove.
+    pub comparisons: u64,
+    /// Nodes boxed for new entries.
+    pub allocations: u64,
+}
+
+#[cfg(feature = "counters")]
+pub fn snapshot() -> Counters {
+    COUNTERS.with(Cell::get)
+}
+
+#[cfg(feature = "counters")]
+pub fn reset() {
+    COUNTERS.with(|counters| counters.set(Counters::default()));
+}
+
+#[cfg(feature = "counters")]
+fn bump(field: fn(&mut Counters) -> &mut u64) {
+    COUNTERS.with(|counters| {
+        let mut counts = counters.get();
+        *field(&mut counts) += 1;
+        counters.set(counts);
+    });
+}
+
+#[inline(always)]
+pub(crate) fn count_rotation() {
+    #[cfg(feature = "counters")]
+    bump(|counts| &mut counts.rotations);
+}
+
+#[inline(always)]
+pub(crate) fn count_comparison() {
+    #[cfg(feature = "counters")]
+    bump(|counts| &mut counts.comparisons);
+}
+
+#[inline(always)]
+pub(crate) fn count_allocation() {
+    #[cfg(feature = "counters")]
+    bump(|counts| &mut counts.allocations);
+}
+
+#[cfg(all(test, feature = "counters"))]
+mod tests {
+    use std::thread;
+
+    use crate::{map::TreapMap, priority::PrioritySource};
+
+    use super::*;
+
+    /// Hands out ever increasing priorities, so that inserting ascending
+    /// keys always takes the same steps.
+    struct Ascending(u64);
+
+    impl PrioritySource for Ascending {
+        fn next_priority(&mut self) -> u64 {
+            self.0 += 1;
+            self.0
+        }
+    }
+
+    #[test]
+    fn counts_work_done() {
+        reset();
+
+        // every key but the first is compared against the root only, hung
+        // on its right and rotated above it
+        let mut map = TreapMap::with_priorities(Ascending(0));
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(
+            snapshot(),
+            Counters {
+                rotations: 999,
+                comparisons: 999,
+                allocations: 1000,
+            }
+        );
+
+        // set operations reuse the nodes they are given, even for the keys
+        // found in both maps
+        let left = TreapMap::from_sorted_iter((0..1000).map(|i| (i, i)));
+        let right = TreapMap::from_sorted_iter((500..1500).map(|i| (i, i)));
+        reset();
+        let union = left.union(right, |_, a, b| a + b);
+        assert_eq!(union.len(), 1500);
+        assert_eq!(snapshot().allocations, 0);
+    }
+
+    #[test]
+    fn counts_are_per_thread() {
+        reset();
+
+        thread::spawn(|| {
+            let mut map = TreapMap::new();
+            for i in 0..100 {
+                map.insert(i, i);
+            }
+            assert_eq!(snapshot().allocations, 100);
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(snapshot(), Counters::default());
+    }
+}
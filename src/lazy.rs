@@ -0,0 +1,501 @@
+use std::{
+    borrow::Borrow,
+    iter::FusedIterator,
+    ops::{Bound, RangeBounds},
+};
+
+use rand::RngCore;
+
+use crate::{
+    map::check_range,
+    monoid::{Action, Monoid},
+    node::{Node, Pieces, Settled, Tree, Walk},
+    priority::{DefaultPriority, PrioritySource},
+};
+
+/// An ordered map that folds its values under `M` and applies actions `A`
+/// to whole ranges of keys at once, a segment tree over sparse keys.
+///
+/// Updating a range cuts its subtree out with two splits and joins it back
+/// afterwards, so it takes O(log n). Updates are left as pending tags on the
+/// subtree root and only handed down as later operations pass through it.
+/// Folding a range walks down to it without handing anything down, and
+/// applies the tags it passes to the folds it picks up. Reading a value may
+/// have to settle those tags, which is why lookups borrow the map mutably.
+///
+/// ```
+/// use treap::{
+///     lazy::LazyTreapMap,
+///     monoid::{Action, Monoid},
+/// };
+///
+/// struct Sum;
+///
+/// impl Monoid<i64> for Sum {
+///     type Agg = i64;
+///
+///     fn identity() -> i64 {
+///         0
+///     }
+///
+///     fn lift(val: &i64) -> i64 {
+///         *val
+///     }
+///
+///     fn combine(left: &i64, right: &i64) -> i64 {
+///         left + right
+///     }
+/// }
+///
+/// #[derive(Clone)]
+/// struct Add(i64);
+///
+/// impl Action<i64, Sum> for Add {
+///     fn apply(&self, val: &mut i64) {
+///         *val += self.0;
+///     }
+///
+///     fn apply_fold(&self, sum: &mut i64, len: usize) {
+///         *sum += self.0 * len as i64;
+///     }
+///
+///     fn compose(&self, then: &Add) -> Add {
+///         Add(self.0 + then.0)
+///     }
+/// }
+///
+/// let mut map: LazyTreapMap<u32, i64, Sum, Add> = LazyTreapMap::new();
+/// for key in [1, 10, 100, 1000] {
+///     map.insert(key, 1);
+/// }
+///
+/// map.update_range(5..500, Add(5));
+/// assert_eq!(map.fold_range(..), 1 + 6 + 6 + 1);
+/// assert_eq!(map.get(&100), Some(&6));
+/// ```
+pub struct LazyTreapMap<K, V, M: Monoid<V>, A: Action<V, M>, P = DefaultPriority> {
+    root: Tree<K, V, M, A>,
+    len: usize,
+    priorities: P,
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>> LazyTreapMap<K, V, M, A> {
+    pub fn new() -> Self {
+        Self::with_priorities(DefaultPriority::new())
+    }
+
+    /// Creates a map whose priorities are drawn from a generator seeded
+    /// with `seed`, so that the same operations always build the same tree.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_priorities(DefaultPriority::with_seed(seed))
+    }
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>, R: RngCore> LazyTreapMap<K, V, M, A, R> {
+    /// Creates a map whose priorities are drawn from `rng`.
+    pub fn with_rng(rng: R) -> Self {
+        Self::with_priorities(rng)
+    }
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>, P> LazyTreapMap<K, V, M, A, P> {
+    pub fn with_priorities(priorities: P) -> Self {
+        Self {
+            root: None,
+            len: 0,
+            priorities,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, key: K, val: V) -> Option<V>
+    where
+        K: Ord,
+        P: PrioritySource,
+    {
+        let priority = self.priorities.next_priority();
+        let res = Node::insert(&mut self.root, key, val, priority);
+
+        if res.is_none() {
+            self.len += 1;
+        }
+
+        res
+    }
+
+    /// Returns the value for `key`, with every pending update applied.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let (_, found) = Node::search(&mut self.root, key);
+
+        // SAFETY: the node lives in the map, which stays borrowed for as
+        // long as the returned reference
+        found.map(|node| unsafe { node.as_ref() }.val())
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.remove_entry(key).map(|(_, val)| val)
+    }
+
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let res = Node::remove(&mut self.root, key);
+
+        if res.is_some() {
+            self.len -= 1;
+        }
+
+        res
+    }
+
+    /// Folds the values whose keys fall within `range`, in O(log n).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end, or if both
+    /// ends are equal and excluded.
+    pub fn fold_range<Q, R>(&self, range: R) -> M::Agg
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        check_range(&range);
+
+        Node::fold(&self.root, &range)
+    }
+
+    /// Applies `action` to every value whose key falls within `range`, in
+    /// O(log n).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end, or if both
+    /// ends are equal and excluded.
+    pub fn update_range<Q, R>(&mut self, range: R, action: A)
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        check_range(&range);
+
+        let (head, mut middle, tail) = self.cut(&range);
+        if let Some(middle) = &mut middle {
+            middle.apply(&action);
+        }
+        self.root = Node::merge(Node::merge(head, middle), tail);
+    }
+
+    /// Takes the tree apart into the entries before `range`, those inside it
+    /// and those after it.
+    fn cut<Q, R>(&mut self, range: &R) -> Pieces<K, V, M, A>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let root = self.root.take();
+
+        let (head, rest) = match range.start_bound() {
+            Bound::Included(start) => {
+                let (less, equal, greater) = Node::split(root, start);
+                (less, Node::merge(equal, greater))
+            }
+            Bound::Excluded(start) => {
+                let (less, equal, greater) = Node::split(root, start);
+                (Node::merge(less, equal), greater)
+            }
+            Bound::Unbounded => (None, root),
+        };
+
+        let (middle, tail) = match range.end_bound() {
+            Bound::Included(end) => {
+                let (less, equal, greater) = Node::split(rest, end);
+                (Node::merge(less, equal), greater)
+            }
+            Bound::Excluded(end) => {
+                let (less, equal, greater) = Node::split(rest, end);
+                (less, Node::merge(equal, greater))
+            }
+            Bound::Unbounded => (rest, None),
+        };
+
+        (head, middle, tail)
+    }
+
+    /// Iterates over the entries of the map, sorted by key, settling pending
+    /// updates along the way.
+    pub fn iter(&mut self) -> Iter<'_, K, V, M, A> {
+        Iter {
+            walk: Walk::new(self.root.as_deref_mut().map(Settled::new)),
+            len: self.len,
+        }
+    }
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>, P: Default> Default for LazyTreapMap<K, V, M, A, P> {
+    fn default() -> Self {
+        Self::with_priorities(P::default())
+    }
+}
+
+impl<'a, K, V, M: Monoid<V>, A: Action<V, M>, P> IntoIterator
+    for &'a mut LazyTreapMap<K, V, M, A, P>
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V, M, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>, P> IntoIterator for LazyTreapMap<K, V, M, A, P> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, M, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            walk: Walk::new(self.root),
+            len: self.len,
+        }
+    }
+}
+
+pub struct Iter<'a, K, V, M: Monoid<V>, A: Action<V, M>> {
+    walk: Walk<Settled<'a, K, V, M, A>>,
+    len: usize,
+}
+
+impl<'a, K, V, M: Monoid<V>, A: Action<V, M>> Iterator for Iter<'a, K, V, M, A> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.walk.next_front()?;
+        self.len -= 1;
+
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>> DoubleEndedIterator for Iter<'_, K, V, M, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let entry = self.walk.next_back()?;
+        self.len -= 1;
+
+        Some(entry)
+    }
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>> ExactSizeIterator for Iter<'_, K, V, M, A> {}
+impl<K, V, M: Monoid<V>, A: Action<V, M>> FusedIterator for Iter<'_, K, V, M, A> {}
+
+pub struct IntoIter<K, V, M: Monoid<V>, A: Action<V, M>> {
+    walk: Walk<Box<Node<K, V, M, A>>>,
+    len: usize,
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>> Iterator for IntoIter<K, V, M, A> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.walk.next_front()?;
+        self.len -= 1;
+
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>> DoubleEndedIterator for IntoIter<K, V, M, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let entry = self.walk.next_back()?;
+        self.len -= 1;
+
+        Some(entry)
+    }
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>> ExactSizeIterator for IntoIter<K, V, M, A> {}
+impl<K, V, M: Monoid<V>, A: Action<V, M>> FusedIterator for IntoIter<K, V, M, A> {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    struct Sum;
+
+    impl Monoid<u64> for Sum {
+        type Agg = u64;
+
+        fn identity() -> u64 {
+            0
+        }
+
+        fn lift(val: &u64) -> u64 {
+            *val
+        }
+
+        fn combine(left: &u64, right: &u64) -> u64 {
+            left.wrapping_add(*right)
+        }
+    }
+
+    // `x * mul + add`, which doesn't commute, so it also checks that pending
+    // tags are composed in the order they were applied
+    #[derive(Clone)]
+    struct Affine {
+        mul: u64,
+        add: u64,
+    }
+
+    impl Action<u64, Sum> for Affine {
+        fn apply(&self, val: &mut u64) {
+            *val = val.wrapping_mul(self.mul).wrapping_add(self.add);
+        }
+
+        fn apply_fold(&self, sum: &mut u64, len: usize) {
+            *sum = sum
+                .wrapping_mul(self.mul)
+                .wrapping_add(self.add.wrapping_mul(len as u64));
+        }
+
+        fn compose(&self, then: &Affine) -> Affine {
+            Affine {
+                mul: self.mul.wrapping_mul(then.mul),
+                add: self.add.wrapping_mul(then.mul).wrapping_add(then.add),
+            }
+        }
+    }
+
+    #[test]
+    fn matches_btreemap() {
+        let mut rng = StdRng::seed_from_u64(14);
+        let mut map: LazyTreapMap<u32, u64, Sum, Affine> = LazyTreapMap::with_seed(14);
+        let mut expected = BTreeMap::new();
+
+        for _ in 0..3000 {
+            let start = rng.gen_range(0..500);
+            let end = rng.gen_range(start..=500);
+
+            match rng.gen_range(0..6) {
+                0 | 1 => {
+                    let val = rng.gen_range(0..100);
+                    assert_eq!(map.insert(start, val), expected.insert(start, val));
+                }
+                2 => {
+                    assert_eq!(map.remove(&start), expected.remove(&start));
+                }
+                3 => {
+                    let action = Affine {
+                        mul: rng.gen_range(1..4),
+                        add: rng.gen_range(0..10),
+                    };
+                    for val in expected.range_mut(start..end).map(|(_, v)| v) {
+                        action.apply(val);
+                    }
+                    map.update_range(start..end, action);
+                }
+                4 => {
+                    let sum = expected
+                        .range(start..=end)
+                        .fold(0u64, |sum, (_, v)| sum.wrapping_add(*v));
+                    assert_eq!(map.fold_range(start..=end), sum);
+                }
+                _ => {
+                    assert_eq!(map.get(&start), expected.get(&start));
+                }
+            }
+        }
+
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter().eq(expected.iter()));
+        assert!(map.iter().rev().eq(expected.iter().rev()));
+        assert!(map.into_iter().eq(expected));
+    }
+
+    #[test]
+    fn unbounded_and_excluded_ranges() {
+        let mut map: LazyTreapMap<u32, u64, Sum, Affine> = LazyTreapMap::new();
+        for key in 0..10 {
+            map.insert(key, 1);
+        }
+
+        let add = |add| Affine { mul: 1, add };
+        map.update_range(..3, add(1));
+        map.update_range((Bound::Excluded(6), Bound::Unbounded), add(2));
+        map.update_range(.., add(10));
+
+        assert_eq!(map.fold_range(..), 3 * 12 + 4 * 11 + 3 * 13);
+        assert_eq!(
+            map.fold_range((Bound::Excluded(2), Bound::Excluded(7))),
+            4 * 11
+        );
+        assert_eq!(map.fold_range(20..), 0);
+    }
+
+    #[test]
+    fn fold_through_pending_tags() {
+        let mut map: LazyTreapMap<u32, u64, Sum, Affine> = LazyTreapMap::with_seed(3);
+        let mut expected: Vec<u64> = (0..64).collect();
+        for key in 0..64 {
+            map.insert(key, key as u64);
+        }
+
+        // overlapping updates leave tags at several depths, which don't
+        // commute with each other
+        for (i, (start, end)) in [(0, 64), (10, 40), (20, 30), (5, 25)]
+            .into_iter()
+            .enumerate()
+        {
+            let action = Affine {
+                mul: 2 + i as u64,
+                add: 7 * i as u64,
+            };
+            for val in &mut expected[start..end] {
+                action.apply(val);
+            }
+            map.update_range(start as u32..end as u32, action);
+        }
+
+        // a shared borrow folds without settling anything
+        let map = &map;
+        for start in 0..64 {
+            for end in start..64 {
+                let sum = expected[start..end]
+                    .iter()
+                    .fold(0u64, |a, b| a.wrapping_add(*b));
+                assert_eq!(map.fold_range(start as u32..end as u32), sum);
+            }
+        }
+    }
+}
@@ -1,3 +1,11 @@
 mod node;
 
+pub mod arena;
+pub mod counters;
+pub mod lazy;
 pub mod map;
+pub mod monoid;
+pub mod persistent;
+pub mod priority;
+pub mod seq;
+pub mod set;
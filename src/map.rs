@@ -1,50 +1,1571 @@
-use std::borrow::Borrow;
+use std::{
+    borrow::Borrow,
+    fmt,
+    iter::FusedIterator,
+    ops::{Bound, Index, RangeBounds},
+};
 
-use crate::node::Node;
+use rand::RngCore;
 
-pub struct TreapMap<K, V> {
-    root: Option<Node<K, V>>,
+use crate::{
+    monoid::Monoid,
+    node::{Node, Spine, Tree, Walk},
+    priority::{DefaultPriority, Fork, PrioritySource},
+};
+
+mod cursor;
+mod entry;
+mod handle;
+mod render;
+#[cfg(feature = "serde")]
+mod serde;
+mod snapshot;
+mod stats;
+mod validate;
+
+pub use cursor::{Cursor, CursorMut};
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use handle::Handle;
+#[cfg(feature = "serde")]
+pub use serde::serde_with_priorities;
+pub use snapshot::{Codec, SnapshotError};
+pub use stats::Stats;
+pub use validate::InvariantError;
+
+pub struct TreapMap<K, V, P = DefaultPriority, M: Monoid<V> = ()> {
+    root: Tree<K, V, M>,
     len: usize,
+    priorities: P,
 }
 
 impl<K, V> TreapMap<K, V> {
     pub fn new() -> Self {
-        Self { root: None, len: 0 }
+        Self::with_priorities(DefaultPriority::new())
+    }
+
+    /// Creates a map whose priorities are drawn from a generator seeded
+    /// with `seed`, so that the same operations always build the same tree.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_priorities(DefaultPriority::with_seed(seed))
+    }
+
+    /// Builds a map out of entries sorted by key in O(n), instead of the
+    /// O(n log n) it takes to insert them one by one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not strictly ascending.
+    pub fn from_sorted_iter<I>(iter: I) -> Self
+    where
+        K: Ord,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut map = Self::new();
+        if map.extend_sorted(&mut iter.into_iter()).is_some() {
+            panic!("keys passed to TreapMap::from_sorted_iter are not strictly ascending");
+        }
+
+        map
+    }
+
+    /// Like [`TreapMap::from_sorted_iter`], taking the entries from a
+    /// vector.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not strictly ascending.
+    pub fn from_sorted_vec(entries: Vec<(K, V)>) -> Self
+    where
+        K: Ord,
+    {
+        Self::from_sorted_iter(entries)
+    }
+}
+
+impl<K, V, R: RngCore> TreapMap<K, V, R> {
+    /// Creates a map whose priorities are drawn from `rng`.
+    pub fn with_rng(rng: R) -> Self {
+        Self::with_priorities(rng)
+    }
+}
+
+impl<K, V, M: Monoid<V>> TreapMap<K, V, DefaultPriority, M> {
+    /// Creates a map that keeps the fold of its values under `M` up to date,
+    /// see [`TreapMap::fold_range`].
+    pub fn with_monoid() -> Self {
+        Self::empty(DefaultPriority::new())
+    }
+}
+
+impl<K, V, P> TreapMap<K, V, P> {
+    pub fn with_priorities(priorities: P) -> Self {
+        Self::empty(priorities)
+    }
+
+    /// Iterates over the entries of the map, sorted by key, with mutable
+    /// references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut {
+            walk: Walk::new(self.root.as_deref_mut()),
+            len: self.len,
+        }
+    }
+
+    /// Like [`TreapMap::range`], with mutable references to the values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end, or if both
+    /// ends are equal and excluded.
+    pub fn range_mut<Q, R>(&mut self, range: R) -> RangeMut<'_, K, V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        check_range(&range);
+
+        RangeMut {
+            walk: Walk::range(self.root.as_deref_mut(), &range),
+        }
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut {
+            inner: self.iter_mut(),
+        }
+    }
+}
+
+impl<K, V, P, M: Monoid<V>> TreapMap<K, V, P, M> {
+    fn empty(priorities: P) -> Self {
+        Self {
+            root: None,
+            len: 0,
+            priorities,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
     pub fn insert(&mut self, key: K, val: V) -> Option<V>
     where
         K: Ord,
+        P: PrioritySource,
+    {
+        let priority = self.priorities.next_priority();
+        let res = Node::insert(&mut self.root, key, val, priority);
+
+        if res.is_none() {
+            self.len += 1;
+        }
+        self.debug_validate();
+
+        res
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.root.as_ref().and_then(|n| n.get(key))
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.remove_entry(key).map(|(_, val)| val)
+    }
+
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let res = Node::remove(&mut self.root, key);
+
+        if res.is_some() {
+            self.len -= 1;
+        }
+        self.debug_validate();
+
+        res
+    }
+
+    /// Returns the entry with the largest key less than or equal to `key`.
+    pub fn floor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.last_before(Bound::Included(key))
+    }
+
+    /// Returns the entry with the smallest key greater than or equal to
+    /// `key`.
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.first_after(Bound::Included(key))
+    }
+
+    /// Returns the entry with the largest key strictly less than `key`.
+    pub fn lower<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.last_before(Bound::Excluded(key))
+    }
+
+    /// Returns the entry with the smallest key strictly greater than `key`.
+    pub fn higher<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.first_after(Bound::Excluded(key))
+    }
+
+    fn first_after<Q>(&self, start: Bound<&Q>) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.root
+            .as_ref()
+            .and_then(|n| n.first_after(start))
+            .map(|n| (n.key(), n.val()))
+    }
+
+    fn last_before<Q>(&self, end: Bound<&Q>) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.root
+            .as_ref()
+            .and_then(|n| n.last_before(end))
+            .map(|n| (n.key(), n.val()))
+    }
+
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        self.root
+            .as_ref()
+            .map(|n| n.first())
+            .map(|n| (n.key(), n.val()))
+    }
+
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        self.root
+            .as_ref()
+            .map(|n| n.last())
+            .map(|n| (n.key(), n.val()))
+    }
+
+    /// Removes the entry with the smallest key.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        self.remove_index(0)
+    }
+
+    /// Removes the entry with the largest key.
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        self.remove_index(self.len.checked_sub(1)?)
+    }
+
+    /// Returns the position of `key` in key order.
+    ///
+    /// If the key is missing, returns `Err` with the position where it would
+    /// be inserted instead, like [`slice::binary_search`].
+    pub fn rank<Q>(&self, key: &Q) -> Result<usize, usize>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.root.as_ref().map_or(Err(0), |n| n.rank(key))
+    }
+
+    /// Returns the entry at position `index` in key order.
+    pub fn get_index(&self, index: usize) -> Option<(&K, &V)> {
+        self.root
+            .as_ref()
+            .and_then(|n| n.select(index))
+            .map(|n| (n.key(), n.val()))
+    }
+
+    /// Removes the entry at position `index` in key order.
+    pub fn remove_index(&mut self, index: usize) -> Option<(K, V)> {
+        let res = Node::remove_index(&mut self.root, index);
+
+        if res.is_some() {
+            self.len -= 1;
+        }
+        self.debug_validate_unordered();
+
+        res
+    }
+
+    /// Moves every entry with a key greater than or equal to `key` into a new
+    /// map, which gets a priority source forked from this map's.
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        P: Fork,
     {
-        let priority = rand::random();
+        let (less, equal, greater) = Node::split(self.root.take(), key);
+        let root = Node::merge(equal, greater);
+        let len = Node::size(&root);
 
-        if let Some(root) = &mut self.root {
-            let res = root.insert(key, val, priority);
+        self.root = less;
+        self.len -= len;
+        self.debug_validate();
 
-            if res.is_none() {
-                self.len += 1;
+        let other = Self {
+            root,
+            len,
+            priorities: self.priorities.fork(),
+        };
+        other.debug_validate();
+
+        other
+    }
+
+    /// Moves every entry of `other` into `self`, leaving `other` empty.
+    ///
+    /// When all keys of one map are less than all keys of the other, the two
+    /// trees are joined along their inner spines in O(log n). Otherwise they
+    /// are merged, and values from `other` overwrite those already in `self`.
+    pub fn append(&mut self, other: &mut Self)
+    where
+        K: Ord,
+    {
+        let (Some(left), Some(right)) = (&self.root, &other.root) else {
+            // the trees change hands, each map keeps its own priorities
+            if self.root.is_none() {
+                std::mem::swap(&mut self.root, &mut other.root);
+                std::mem::swap(&mut self.len, &mut other.len);
             }
+            return;
+        };
 
-            res
+        let root = if left.last().key() < right.first().key() {
+            Node::merge(self.root.take(), other.root.take())
+        } else if right.last().key() < left.first().key() {
+            Node::merge(other.root.take(), self.root.take())
         } else {
-            self.root = Some(Node::new(key, val, priority));
-            self.len += 1;
+            Node::union(self.root.take(), other.root.take(), &mut |_, _, val| val)
+        };
+
+        self.len = Node::size(&root);
+        self.root = root;
+        other.len = 0;
+        self.debug_validate();
+    }
+
+    /// Combines both maps, calling `resolve` with the key and the values
+    /// from `self` and `other` for every key present in both.
+    ///
+    /// Works by splitting and joining subtrees rather than reinserting, so
+    /// it takes O(m log(n / m + 1)) for maps of size m <= n.
+    pub fn union<F>(mut self, mut other: Self, mut resolve: F) -> Self
+    where
+        K: Ord,
+        F: FnMut(&K, V, V) -> V,
+    {
+        let root = Node::union(self.root.take(), other.root.take(), &mut resolve);
+        self.with_root(root)
+    }
+
+    /// Keeps the entries of `self` whose keys are also in `other`.
+    ///
+    /// Takes O(m log(n / m + 1)) for maps of size m <= n.
+    pub fn intersection(mut self, mut other: Self) -> Self
+    where
+        K: Ord,
+    {
+        let root = Node::intersection(self.root.take(), other.root.take());
+        self.with_root(root)
+    }
+
+    /// Keeps the entries of `self` whose keys are not in `other`.
+    ///
+    /// Takes O(m log(n / m + 1)) for maps of size m <= n.
+    pub fn difference(mut self, mut other: Self) -> Self
+    where
+        K: Ord,
+    {
+        let root = Node::difference(self.root.take(), other.root.take());
+        self.with_root(root)
+    }
 
-            None
+    /// Keeps the entries whose keys are in exactly one of the two maps.
+    ///
+    /// Takes O(m log(n / m + 1)) for maps of size m <= n.
+    pub fn symmetric_difference(mut self, mut other: Self) -> Self
+    where
+        K: Ord,
+    {
+        let root = Node::symmetric_difference(self.root.take(), other.root.take());
+        self.with_root(root)
+    }
+
+    fn with_root(mut self, root: Tree<K, V, M>) -> Self
+    where
+        K: Ord,
+    {
+        self.len = Node::size(&root);
+        self.root = root;
+        self.debug_validate();
+
+        self
+    }
+
+    /// Takes entries from `iter` for as long as their keys are strictly
+    /// ascending, builds them into a tree in linear time and merges it into
+    /// the map, with the new values replacing the old ones.
+    ///
+    /// Returns the first entry that was out of order, if any.
+    fn extend_sorted<I>(&mut self, iter: &mut I) -> Option<(K, V)>
+    where
+        K: Ord,
+        P: PrioritySource,
+        I: Iterator<Item = (K, V)>,
+    {
+        let mut spine = Spine::new();
+        let mut rest = None;
+
+        for (key, val) in iter {
+            if spine.last_key().is_some_and(|last| *last >= key) {
+                rest = Some((key, val));
+                break;
+            }
+            spine.push(key, val, self.priorities.next_priority());
         }
+
+        let root = Node::union(self.root.take(), spine.finish(), &mut |_, _, new| new);
+        self.len = Node::size(&root);
+        self.root = root;
+        self.debug_validate();
+
+        rest
     }
 
-    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    /// Iterates over the entries of the map, sorted by key.
+    pub fn iter(&self) -> Iter<'_, K, V, M> {
+        Iter {
+            walk: Walk::new(self.root.as_deref()),
+            len: self.len,
+        }
+    }
+
+    /// Iterates over the entries whose keys fall within `range`, sorted by
+    /// key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end, or if both
+    /// ends are equal and excluded.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V, M>
     where
         K: Borrow<Q> + Ord,
         Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
     {
-        self.root.as_ref().and_then(|n| n.get(key))
+        check_range(&range);
+
+        Range {
+            walk: Walk::range(self.root.as_deref(), &range),
+        }
+    }
+
+    /// Folds the values whose keys fall within `range` under the map's
+    /// monoid, in O(log n).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end, or if both
+    /// ends are equal and excluded.
+    pub fn fold_range<Q, R>(&self, range: R) -> M::Agg
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        check_range(&range);
+
+        Node::fold(&self.root, &range)
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V, M> {
+        Keys { inner: self.iter() }
+    }
+
+    pub fn values(&self) -> Values<'_, K, V, M> {
+        Values { inner: self.iter() }
+    }
+
+    pub fn into_keys(self) -> IntoKeys<K, V, M> {
+        IntoKeys {
+            inner: self.into_iter(),
+        }
+    }
+
+    pub fn into_values(self) -> IntoValues<K, V, M> {
+        IntoValues {
+            inner: self.into_iter(),
+        }
+    }
+}
+
+pub(crate) fn check_range<Q, R>(range: &R)
+where
+    Q: Ord + ?Sized,
+    R: RangeBounds<Q>,
+{
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Excluded(start), Bound::Excluded(end)) if start == end => {
+            panic!("range start and end are equal and excluded in TreapMap")
+        }
+        (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) if start > end => {
+            panic!("range start is greater than range end in TreapMap")
+        }
+        _ => {}
     }
 }
 
-impl<K, V> Default for TreapMap<K, V> {
+impl<K, V, P: Default, M: Monoid<V>> Default for TreapMap<K, V, P, M> {
     fn default() -> Self {
-        Self::new()
+        Self::empty(P::default())
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, P, M: Monoid<V>> fmt::Debug for TreapMap<K, V, P, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Accesses values by their position in key order.
+///
+/// # Panics
+///
+/// Panics if the index is out of bounds.
+impl<K, V, P, M: Monoid<V>> Index<usize> for TreapMap<K, V, P, M> {
+    type Output = V;
+
+    fn index(&self, index: usize) -> &V {
+        match self.get_index(index) {
+            Some((_, val)) => val,
+            None => panic!(
+                "index out of bounds: the len is {} but the index is {}",
+                self.len, index
+            ),
+        }
+    }
+}
+
+impl<'a, K, V, P, M: Monoid<V>> IntoIterator for &'a TreapMap<K, V, P, M> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V, M>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K, V, P> IntoIterator for &'a mut TreapMap<K, V, P> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<K: Ord, V, P, M> FromIterator<(K, V)> for TreapMap<K, V, P, M>
+where
+    P: PrioritySource + Default,
+    M: Monoid<V>,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+
+        map
+    }
+}
+
+/// Runs of entries with ascending keys are built into a tree in linear time
+/// and then merged in, so extending with sorted entries takes O(n) rather
+/// than O(n log n).
+impl<K: Ord, V, P: PrioritySource, M: Monoid<V>> Extend<(K, V)> for TreapMap<K, V, P, M> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let mut iter = iter.into_iter();
+        let Some(mut next) = self.extend_sorted(&mut iter) else {
+            return;
+        };
+
+        // a key out of order ends the run, and begins the next one
+        loop {
+            let mut run = std::iter::once(next).chain(iter.by_ref());
+            match self.extend_sorted(&mut run) {
+                Some(entry) => next = entry,
+                None => return,
+            }
+        }
+    }
+}
+
+impl<K, V, P, M: Monoid<V>> IntoIterator for TreapMap<K, V, P, M> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, M>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            walk: Walk::new(self.root),
+            len: self.len,
+        }
+    }
+}
+
+// The walk runs dry on its own once both ends meet, `len` is only kept
+// around for the size hint.
+macro_rules! entry_iterator {
+    ($name:ident $(<$m:ident>)?, $item:ty) => {
+        impl<'a, K, V $(, $m: Monoid<V>)?> Iterator for $name<'a, K, V $(, $m)?> {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                let entry = self.walk.next_front()?;
+                self.len -= 1;
+
+                Some(entry)
+            }
+
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                (self.len, Some(self.len))
+            }
+        }
+
+        impl<'a, K, V $(, $m: Monoid<V>)?> DoubleEndedIterator for $name<'a, K, V $(, $m)?> {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                let entry = self.walk.next_back()?;
+                self.len -= 1;
+
+                Some(entry)
+            }
+        }
+
+        impl<K, V $(, $m: Monoid<V>)?> ExactSizeIterator for $name<'_, K, V $(, $m)?> {}
+        impl<K, V $(, $m: Monoid<V>)?> FusedIterator for $name<'_, K, V $(, $m)?> {}
+    };
+}
+
+macro_rules! projection {
+    ($name:ident $(<$m:ident>)?, $item:ty, $proj:expr) => {
+        impl<'a, K, V $(, $m: Monoid<V>)?> Iterator for $name<'a, K, V $(, $m)?> {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.inner.next().map($proj)
+            }
+
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                self.inner.size_hint()
+            }
+        }
+
+        impl<'a, K, V $(, $m: Monoid<V>)?> DoubleEndedIterator for $name<'a, K, V $(, $m)?> {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                self.inner.next_back().map($proj)
+            }
+        }
+
+        impl<K, V $(, $m: Monoid<V>)?> ExactSizeIterator for $name<'_, K, V $(, $m)?> {}
+        impl<K, V $(, $m: Monoid<V>)?> FusedIterator for $name<'_, K, V $(, $m)?> {}
+    };
+}
+
+pub struct Iter<'a, K, V, M: Monoid<V> = ()> {
+    walk: Walk<&'a Node<K, V, M>>,
+    len: usize,
+}
+
+impl<K, V, M: Monoid<V>> Clone for Iter<'_, K, V, M> {
+    fn clone(&self) -> Self {
+        Self {
+            walk: self.walk.clone(),
+            len: self.len,
+        }
+    }
+}
+
+entry_iterator!(Iter<M>, (&'a K, &'a V));
+
+pub struct IterMut<'a, K, V> {
+    walk: Walk<&'a mut Node<K, V>>,
+    len: usize,
+}
+
+entry_iterator!(IterMut, (&'a K, &'a mut V));
+
+pub struct IntoIter<K, V, M: Monoid<V> = ()> {
+    walk: Walk<Box<Node<K, V, M>>>,
+    len: usize,
+}
+
+impl<K, V, M: Monoid<V>> Iterator for IntoIter<K, V, M> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.walk.next_front()?;
+        self.len -= 1;
+
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V, M: Monoid<V>> DoubleEndedIterator for IntoIter<K, V, M> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let entry = self.walk.next_back()?;
+        self.len -= 1;
+
+        Some(entry)
+    }
+}
+
+impl<K, V, M: Monoid<V>> ExactSizeIterator for IntoIter<K, V, M> {}
+impl<K, V, M: Monoid<V>> FusedIterator for IntoIter<K, V, M> {}
+
+pub struct Range<'a, K, V, M: Monoid<V> = ()> {
+    walk: Walk<&'a Node<K, V, M>>,
+}
+
+impl<K, V, M: Monoid<V>> Clone for Range<'_, K, V, M> {
+    fn clone(&self) -> Self {
+        Self {
+            walk: self.walk.clone(),
+        }
+    }
+}
+
+pub struct RangeMut<'a, K, V> {
+    walk: Walk<&'a mut Node<K, V>>,
+}
+
+macro_rules! range_iterator {
+    ($name:ident $(<$m:ident>)?, $item:ty) => {
+        impl<'a, K, V $(, $m: Monoid<V>)?> Iterator for $name<'a, K, V $(, $m)?> {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.walk.next_front()
+            }
+        }
+
+        impl<'a, K, V $(, $m: Monoid<V>)?> DoubleEndedIterator for $name<'a, K, V $(, $m)?> {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                self.walk.next_back()
+            }
+        }
+
+        impl<K, V $(, $m: Monoid<V>)?> FusedIterator for $name<'_, K, V $(, $m)?> {}
+    };
+}
+
+range_iterator!(Range<M>, (&'a K, &'a V));
+range_iterator!(RangeMut, (&'a K, &'a mut V));
+
+pub struct Keys<'a, K, V, M: Monoid<V> = ()> {
+    inner: Iter<'a, K, V, M>,
+}
+
+impl<K, V, M: Monoid<V>> Clone for Keys<'_, K, V, M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+projection!(Keys<M>, &'a K, |(key, _)| key);
+
+pub struct Values<'a, K, V, M: Monoid<V> = ()> {
+    inner: Iter<'a, K, V, M>,
+}
+
+impl<K, V, M: Monoid<V>> Clone for Values<'_, K, V, M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+projection!(Values<M>, &'a V, |(_, val)| val);
+
+pub struct ValuesMut<'a, K, V> {
+    inner: IterMut<'a, K, V>,
+}
+
+projection!(ValuesMut, &'a mut V, |(_, val)| val);
+
+pub struct IntoKeys<K, V, M: Monoid<V> = ()> {
+    inner: IntoIter<K, V, M>,
+}
+
+pub struct IntoValues<K, V, M: Monoid<V> = ()> {
+    inner: IntoIter<K, V, M>,
+}
+
+impl<K, V, M: Monoid<V>> Iterator for IntoKeys<K, V, M> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, _)| key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V, M: Monoid<V>> DoubleEndedIterator for IntoKeys<K, V, M> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(key, _)| key)
+    }
+}
+
+impl<K, V, M: Monoid<V>> ExactSizeIterator for IntoKeys<K, V, M> {}
+impl<K, V, M: Monoid<V>> FusedIterator for IntoKeys<K, V, M> {}
+
+impl<K, V, M: Monoid<V>> Iterator for IntoValues<K, V, M> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, val)| val)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V, M: Monoid<V>> DoubleEndedIterator for IntoValues<K, V, M> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, val)| val)
+    }
+}
+
+impl<K, V, M: Monoid<V>> ExactSizeIterator for IntoValues<K, V, M> {}
+impl<K, V, M: Monoid<V>> FusedIterator for IntoValues<K, V, M> {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use super::*;
+
+    #[test]
+    fn remove() {
+        let mut map = TreapMap::new();
+
+        for i in 0..100 {
+            map.insert(i, i * 2);
+        }
+
+        for i in (0..100).step_by(2) {
+            assert_eq!(map.remove(&i), Some(i * 2));
+        }
+        assert_eq!(map.remove(&0), None);
+        assert_eq!(map.len(), 50);
+
+        for i in 0..100 {
+            let expected = if i % 2 == 0 { None } else { Some(&(i * 2)) };
+            assert_eq!(map.get(&i), expected);
+        }
+    }
+
+    #[test]
+    fn remove_entry() {
+        let mut map = TreapMap::new();
+        map.insert(String::from("a"), 1);
+        map.insert(String::from("b"), 2);
+
+        assert_eq!(map.remove_entry("a"), Some((String::from("a"), 1)));
+        assert_eq!(map.remove_entry("a"), None);
+        assert_eq!(map.len(), 1);
+
+        assert_eq!(map.remove("b"), Some(2));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn iter() {
+        let mut map = TreapMap::new();
+        for i in (0..100).rev() {
+            map.insert(i, i * 2);
+        }
+
+        let entries: Vec<_> = map.iter().map(|(&k, &v)| (k, v)).collect();
+        let expected: Vec<_> = (0..100).map(|i| (i, i * 2)).collect();
+        assert_eq!(entries, expected);
+
+        let keys: Vec<_> = map.keys().rev().copied().collect();
+        let expected: Vec<_> = (0..100).rev().collect();
+        assert_eq!(keys, expected);
+
+        assert_eq!(map.values().len(), 100);
+    }
+
+    #[test]
+    fn iter_both_ends() {
+        let mut map = TreapMap::new();
+        for i in 0..10 {
+            map.insert(i, ());
+        }
+
+        let mut iter = map.keys();
+        let mut seen = Vec::new();
+        while let Some(&front) = iter.next() {
+            seen.push(front);
+            if let Some(&back) = iter.next_back() {
+                seen.push(back);
+            }
+            assert_eq!(iter.len(), 10 - seen.len());
+        }
+
+        seen.sort();
+        assert_eq!(seen, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn iter_mut() {
+        let mut map = TreapMap::new();
+        for i in 0..10 {
+            map.insert(i, i);
+        }
+
+        for (k, v) in &mut map {
+            *v += k;
+        }
+        for v in map.values_mut() {
+            *v += 1;
+        }
+
+        let values: Vec<_> = map.into_values().collect();
+        assert_eq!(values, (0..10).map(|i| i * 2 + 1).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn into_iter() {
+        let mut map = TreapMap::new();
+        for i in 0..10 {
+            map.insert(i.to_string(), i);
+        }
+
+        let mut iter = map.into_iter();
+        assert_eq!(iter.next(), Some((String::from("0"), 0)));
+        assert_eq!(iter.next_back(), Some((String::from("9"), 9)));
+        assert_eq!(iter.len(), 8);
+        drop(iter);
+    }
+
+    #[test]
+    fn range() {
+        let mut map = TreapMap::new();
+        for i in 0..100 {
+            map.insert(i * 2, i);
+        }
+
+        let keys = |r: Range<'_, i32, i32>| r.map(|(&k, _)| k).collect::<Vec<_>>();
+
+        assert_eq!(keys(map.range(10..20)), vec![10, 12, 14, 16, 18]);
+        assert_eq!(keys(map.range(9..=20)), vec![10, 12, 14, 16, 18, 20]);
+        assert_eq!(keys(map.range(195..)), vec![196, 198]);
+        assert_eq!(keys(map.range(..3)), vec![0, 2]);
+        assert_eq!(keys(map.range(11..12)), Vec::<i32>::new());
+        assert_eq!(keys(map.range(500..)), Vec::<i32>::new());
+        assert_eq!(keys(map.range(..)).len(), 100);
+
+        let mut range = map.range((Bound::Excluded(10), Bound::Excluded(20)));
+        assert_eq!(range.next_back(), Some((&18, &9)));
+        assert_eq!(range.next(), Some((&12, &6)));
+        assert_eq!(keys(range), vec![14, 16]);
+    }
+
+    #[test]
+    fn range_borrowed() {
+        let mut map = TreapMap::new();
+        for word in ["apple", "banana", "cherry", "date"] {
+            map.insert(String::from(word), word.len());
+        }
+
+        let found: Vec<_> = map
+            .range::<str, _>((Bound::Included("b"), Bound::Excluded("d")))
+            .map(|(k, _)| k.as_str())
+            .collect();
+        assert_eq!(found, vec!["banana", "cherry"]);
+    }
+
+    #[test]
+    fn range_mut() {
+        let mut map = TreapMap::new();
+        for i in 0..10 {
+            map.insert(i, 0);
+        }
+
+        for (_, v) in map.range_mut(3..6) {
+            *v = 1;
+        }
+
+        let values: Vec<_> = map.values().copied().collect();
+        assert_eq!(values, vec![0, 0, 0, 1, 1, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn split_off() {
+        let mut map = TreapMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+
+        let right = map.split_off(&40);
+        assert_eq!(map.len(), 40);
+        assert_eq!(right.len(), 60);
+        assert!(map.keys().copied().eq(0..40));
+        assert!(right.keys().copied().eq(40..100));
+
+        let mut map = right;
+        let right = map.split_off(&40);
+        assert!(map.is_empty());
+        assert_eq!(right.len(), 60);
+    }
+
+    #[test]
+    fn split_off_forks_priorities() {
+        let mut left = TreapMap::with_seed(5);
+        left.insert(0, 0);
+        left.insert(10, 10);
+        let mut right = left.split_off(&5);
+
+        // the halves must not hand out the same priorities from here on
+        let next = |map: &mut TreapMap<i32, i32>| map.priorities.next_priority();
+        assert!((0..100).all(|_| next(&mut left) != next(&mut right)));
+    }
+
+    #[test]
+    fn append_disjoint() {
+        let mut left = TreapMap::new();
+        let mut right = TreapMap::new();
+        for i in 0..50 {
+            left.insert(i, i);
+            right.insert(i + 50, i + 50);
+        }
+
+        right.append(&mut left);
+        assert!(left.is_empty());
+        assert_eq!(right.len(), 100);
+        assert!(right
+            .iter()
+            .map(|(&k, &v)| (k, v))
+            .eq((0..100).map(|i| (i, i))));
+    }
+
+    #[test]
+    fn append_to_empty_keeps_priorities() {
+        let mut empty = TreapMap::with_seed(1);
+        let mut other = TreapMap::with_seed(2);
+        other.insert(0, 0);
+
+        empty.append(&mut other);
+        empty.insert(1, 1);
+        assert!(other.is_empty());
+        assert_eq!(empty.len(), 2);
+
+        let expected = DefaultPriority::with_seed(1).next_priority();
+        let tree = empty.pretty_tree(usize::MAX, i32::to_string, i32::to_string);
+        assert!(tree.contains(&format!("1: 1 ({expected})")), "{tree}");
+    }
+
+    #[test]
+    fn append_overlapping() {
+        let mut a = TreapMap::new();
+        let mut b = TreapMap::new();
+        for i in 0..60 {
+            a.insert(i, "a");
+        }
+        for i in (40..100).rev() {
+            b.insert(i, "b");
+        }
+
+        a.append(&mut b);
+        assert!(b.is_empty());
+        assert_eq!(a.len(), 100);
+        for (&k, &v) in &a {
+            assert_eq!(v, if k < 40 { "a" } else { "b" });
+        }
+    }
+
+    #[test]
+    fn order_statistics() {
+        let mut map = TreapMap::new();
+        for i in (0..100).rev() {
+            map.insert(i * 10, i);
+        }
+
+        for i in 0..100 {
+            assert_eq!(map.get_index(i), Some((&(i * 10), &i)));
+            assert_eq!(map[i], i);
+            assert_eq!(map.rank(&(i * 10)), Ok(i));
+            assert_eq!(map.rank(&(i * 10 + 5)), Err(i + 1));
+        }
+        assert_eq!(map.get_index(100), None);
+
+        assert_eq!(map.remove_index(10), Some((100, 10)));
+        assert_eq!(map.remove_index(99), None);
+        assert_eq!(map.len(), 99);
+        assert_eq!(map.rank(&110), Ok(10));
+
+        let right = map.split_off(&500);
+        assert_eq!(right.get_index(0), Some((&500, &50)));
+        assert_eq!(right.rank(&990), Ok(49));
+    }
+
+    #[test]
+    fn neighbours() {
+        let mut map = TreapMap::new();
+        let mut expected = BTreeMap::new();
+        for i in (0..60).map(|i| i * 37 % 60 * 5) {
+            map.insert(i.to_string(), i);
+            expected.insert(i.to_string(), i);
+        }
+
+        for i in 0..310 {
+            let key = i.to_string();
+            let key = key.as_str();
+            let owned = |entry: Option<(&String, &i32)>| entry.map(|(k, v)| (k.clone(), *v));
+
+            assert_eq!(
+                owned(map.floor(key)),
+                owned(
+                    expected
+                        .range::<str, _>((Bound::Unbounded, Bound::Included(key)))
+                        .next_back()
+                )
+            );
+            assert_eq!(
+                owned(map.ceiling(key)),
+                owned(
+                    expected
+                        .range::<str, _>((Bound::Included(key), Bound::Unbounded))
+                        .next()
+                )
+            );
+            assert_eq!(
+                owned(map.lower(key)),
+                owned(
+                    expected
+                        .range::<str, _>((Bound::Unbounded, Bound::Excluded(key)))
+                        .next_back()
+                )
+            );
+            assert_eq!(
+                owned(map.higher(key)),
+                owned(
+                    expected
+                        .range::<str, _>((Bound::Excluded(key), Bound::Unbounded))
+                        .next()
+                )
+            );
+        }
+
+        assert_eq!(map.first_key_value(), expected.first_key_value());
+        assert_eq!(map.last_key_value(), expected.last_key_value());
+        while let Some(entry) = map.pop_first() {
+            assert_eq!(Some(entry), expected.pop_first());
+            assert_eq!(map.pop_last(), expected.pop_last());
+        }
+        assert!(map.is_empty());
+        assert_eq!(map.first_key_value(), None);
+        assert_eq!(map.pop_last(), None);
+    }
+
+    #[test]
+    fn sizes_survive_entry() {
+        let mut map = TreapMap::new();
+        for i in 0..50 {
+            *map.entry(i % 25).or_insert(0) += 1;
+        }
+        if let Entry::Occupied(entry) = map.entry(0) {
+            entry.remove();
+        }
+
+        for i in 0..24 {
+            assert_eq!(map.get_index(i), Some((&(i + 1), &2)));
+        }
+    }
+
+    fn set_op_inputs() -> (TreapMap<u32, char>, TreapMap<u32, char>) {
+        let mut a = TreapMap::new();
+        let mut b = TreapMap::new();
+        for i in 0..300 {
+            a.insert(i * 7 % 500, 'a');
+            b.insert(i * 11 % 400, 'b');
+        }
+
+        (a, b)
+    }
+
+    #[test]
+    fn union() {
+        let (a, b) = set_op_inputs();
+        let expected: BTreeMap<_, _> = b.iter().chain(a.iter()).map(|(&k, &v)| (k, v)).collect();
+
+        let map = a.union(b, |_, x, y| {
+            assert_eq!((x, y), ('a', 'b'));
+            x
+        });
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter().map(|(&k, &v)| (k, v)).eq(expected));
+    }
+
+    #[test]
+    fn intersection_and_differences() {
+        let keys = |map: &TreapMap<u32, char>| map.keys().copied().collect::<BTreeSet<_>>();
+
+        let (a, b) = set_op_inputs();
+        let (ka, kb) = (keys(&a), keys(&b));
+        let map = a.intersection(b);
+        assert_eq!(keys(&map), &ka & &kb);
+        assert_eq!(map.len(), (&ka & &kb).len());
+        assert!(map.values().all(|&v| v == 'a'));
+
+        let (a, b) = set_op_inputs();
+        let map = b.intersection(a);
+        assert!(map.values().all(|&v| v == 'b'));
+
+        let (a, b) = set_op_inputs();
+        let map = a.difference(b);
+        assert_eq!(keys(&map), &ka - &kb);
+        assert_eq!(map.len(), (&ka - &kb).len());
+
+        let (a, b) = set_op_inputs();
+        let map = a.symmetric_difference(b);
+        assert_eq!(keys(&map), &ka ^ &kb);
+        assert_eq!(map.len(), (&ka ^ &kb).len());
+    }
+
+    #[test]
+    fn seeded_shape_is_reproducible() {
+        let build = |map: &mut TreapMap<u32, u32, _>| {
+            for i in 0..100 {
+                map.insert(i * 37 % 101, i);
+            }
+        };
+
+        let mut a = TreapMap::with_seed(7);
+        let mut b = TreapMap::with_seed(7);
+        build(&mut a);
+        build(&mut b);
+
+        fn preorder(node: Option<&Node<u32, u32>>, out: &mut Vec<(u32, u64)>) {
+            if let Some(node) = node {
+                out.push((*node.key(), node.priority()));
+                preorder(node.left(), out);
+                preorder(node.right(), out);
+            }
+        }
+
+        let (mut shape_a, mut shape_b) = (Vec::new(), Vec::new());
+        preorder(a.root.as_deref(), &mut shape_a);
+        preorder(b.root.as_deref(), &mut shape_b);
+        assert_eq!(shape_a, shape_b);
+    }
+
+    #[test]
+    fn from_sorted() {
+        fn check(node: Option<&Node<u32, u32>>, bound: u64) -> usize {
+            node.map_or(0, |node| {
+                assert!(node.priority() <= bound);
+                1 + check(node.left(), node.priority()) + check(node.right(), node.priority())
+            })
+        }
+
+        let map = TreapMap::from_sorted_iter((0..10_000).map(|i| (i * 2, i)));
+        assert_eq!(check(map.root.as_deref(), u64::MAX), 10_000);
+        assert_eq!(map.len(), 10_000);
+        assert!(map.keys().copied().eq((0..20_000).step_by(2)));
+        assert_eq!(map.get(&1234), Some(&617));
+        for i in 0..10_000 {
+            assert_eq!(map.get_index(i as usize), Some((&(i * 2), &i)));
+        }
+
+        let map = TreapMap::from_sorted_vec(vec![(1, 'a'), (2, 'b'), (3, 'c')]);
+        assert_eq!(map.get_index(1), Some((&2, &'b')));
+        assert!(TreapMap::<u8, u8>::from_sorted_vec(Vec::new()).is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn from_sorted_out_of_order() {
+        TreapMap::from_sorted_vec(vec![(1, ()), (3, ()), (2, ())]);
+    }
+
+    #[test]
+    fn collect_and_extend() {
+        let mut expected = BTreeMap::new();
+
+        // sorted runs, a repeated key, and scattered keys, some overwriting
+        let entries: Vec<_> = (0..500)
+            .chain(200..300)
+            .chain([7, 7])
+            .chain((0..300).map(|i| i * 7 % 1000))
+            .enumerate()
+            .map(|(val, key)| (key, val))
+            .collect();
+
+        let mut map: TreapMap<_, _> = entries[..400].iter().copied().collect();
+        expected.extend(entries[..400].iter().copied());
+        assert!(map.iter().eq(expected.iter()));
+
+        map.extend(entries[400..].iter().copied());
+        expected.extend(entries[400..].iter().copied());
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter().eq(expected.iter()));
+
+        let set: crate::set::TreapSet<_> = (0..100).rev().chain(50..150).collect();
+        assert!(set.iter().copied().eq(0..150));
+    }
+
+    #[test]
+    fn with_rng() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut map = TreapMap::with_rng(StdRng::seed_from_u64(1));
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+
+        assert!(map.keys().copied().eq(0..100));
+    }
+
+    /// Hands out ever increasing priorities, so every new node is rotated
+    /// all the way up to the root.
+    struct Ascending(u64);
+
+    impl PrioritySource for Ascending {
+        fn next_priority(&mut self) -> u64 {
+            self.0 += 1;
+            self.0
+        }
+    }
+
+    impl Fork for Ascending {
+        fn fork(&mut self) -> Self {
+            Ascending(self.0)
+        }
+    }
+
+    /// Length of the degenerate spines below, kept short when every insert
+    /// validates the whole tree, or when running under Miri.
+    const SPINE: u32 = if cfg!(any(feature = "debug-invariants", miri)) {
+        2_000
+    } else {
+        1_000_000
+    };
+
+    #[test]
+    fn degenerate_left_spine() {
+        const N: u32 = SPINE;
+
+        // ascending keys each end up as the new root, with everything else
+        // hanging off a single left spine
+        let mut map = TreapMap::with_priorities(Ascending(0));
+        for i in 0..N {
+            map.insert(i, i);
+        }
+
+        assert_eq!(map.len(), N as usize);
+        assert_eq!(map.get(&0), Some(&0));
+        assert_eq!(map.get(&N), None);
+        assert_eq!(map.rank(&1), Ok(1));
+        assert_eq!(map.remove(&1), Some(1));
+        assert_eq!(map.remove_index(0), Some((0, 0)));
+        assert_eq!(map.get_index(0), Some((&2, &2)));
+
+        let mut iter = map.into_iter();
+        assert_eq!(iter.next(), Some((2, 2)));
+        assert_eq!(iter.next_back(), Some((N - 1, N - 1)));
+    }
+
+    #[test]
+    fn degenerate_right_spine() {
+        const N: u32 = SPINE;
+
+        let mut map = TreapMap::with_priorities(Ascending(0));
+        for i in (0..N).rev() {
+            map.insert(i, i);
+        }
+
+        // re-inserting the deepest key bumps its priority above everything
+        // else and rotates it through the whole spine
+        assert_eq!(map.insert(N - 1, 0), Some(N - 1));
+        assert_eq!(map.get(&(N - 1)), Some(&0));
+        assert_eq!(map.len(), N as usize);
+        assert_eq!(map.get_index(N as usize - 1), Some((&(N - 1), &0)));
+    }
+
+    #[test]
+    fn degenerate_spine_split_and_join() {
+        const N: u32 = SPINE;
+
+        let mut map = TreapMap::with_priorities(Ascending(0));
+        for i in 0..N {
+            map.insert(i, i);
+        }
+
+        // splitting and merging walk the whole spine
+        let mut right = map.split_off(&(N / 2));
+        assert_eq!(map.len(), (N / 2) as usize);
+        assert_eq!(right.first_key_value(), Some((&(N / 2), &(N / 2))));
+
+        map.append(&mut right);
+        assert_eq!(map.len(), N as usize);
+        assert!(right.is_empty());
+
+        // so does every level of a set operation on two interleaved spines
+        let mut odd = TreapMap::with_priorities(Ascending(N as u64));
+        for i in (1..N).step_by(2) {
+            odd.insert(i, 0);
+        }
+        let even = map.difference(odd);
+        assert_eq!(even.len(), N.div_ceil(2) as usize);
+        assert!(even.keys().all(|k| k % 2 == 0));
+    }
+
+    struct Sum;
+
+    impl Monoid<u64> for Sum {
+        type Agg = u64;
+
+        fn identity() -> u64 {
+            0
+        }
+
+        fn lift(val: &u64) -> u64 {
+            *val
+        }
+
+        fn combine(left: &u64, right: &u64) -> u64 {
+            left + right
+        }
+    }
+
+    // not commutative, so it also checks that folds keep key order
+    struct Concat;
+
+    impl Monoid<u64> for Concat {
+        type Agg = Vec<u64>;
+
+        fn identity() -> Vec<u64> {
+            Vec::new()
+        }
+
+        fn lift(val: &u64) -> Vec<u64> {
+            vec![*val]
+        }
+
+        fn combine(left: &Vec<u64>, right: &Vec<u64>) -> Vec<u64> {
+            left.iter().chain(right).copied().collect()
+        }
+    }
+
+    #[test]
+    fn fold_range() {
+        let mut map: TreapMap<u64, u64, _, Sum> = TreapMap::with_monoid();
+        let mut expected = BTreeMap::new();
+
+        for i in 0..1000 {
+            let key = i * 7 % 300;
+            map.insert(key, i);
+            expected.insert(key, i);
+
+            if i % 3 == 0 {
+                map.remove(&(i % 300));
+                expected.remove(&(i % 300));
+            }
+        }
+
+        for (start, end) in [(0, 300), (10, 20), (57, 58), (100, 101), (250, 400)] {
+            assert_eq!(
+                map.fold_range(start..end),
+                expected.range(start..end).map(|(_, v)| v).sum::<u64>()
+            );
+            assert_eq!(
+                map.fold_range(start..=end),
+                expected.range(start..=end).map(|(_, v)| v).sum::<u64>()
+            );
+        }
+        assert_eq!(map.fold_range(..), expected.values().sum::<u64>());
+        assert_eq!(map.fold_range(500..), 0);
+    }
+
+    #[test]
+    fn fold_keeps_order() {
+        let mut map: TreapMap<u64, u64, _, Concat> = TreapMap::with_monoid();
+        for i in (0..100).rev() {
+            map.insert(i, i);
+        }
+        map.insert(40, 400);
+        map.remove_index(10);
+
+        let mut other: TreapMap<u64, u64, _, Concat> = TreapMap::with_monoid();
+        for i in 80..120 {
+            other.insert(i, i + 1000);
+        }
+        map.append(&mut other);
+        let tail = map.split_off(&90);
+
+        let values: Vec<_> = map.values().copied().collect();
+        assert_eq!(map.fold_range(..), values);
+        assert_eq!(map.fold_range(30..50), values[29..49]);
+        assert_eq!(
+            tail.fold_range(..),
+            tail.values().copied().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_out_of_bounds() {
+        let map: TreapMap<i32, i32> = TreapMap::new();
+        let _ = map[0];
+    }
+
+    #[test]
+    #[should_panic]
+    fn range_backwards() {
+        let map: TreapMap<i32, ()> = TreapMap::new();
+        map.range((Bound::Included(5), Bound::Excluded(3)));
     }
 }
@@ -0,0 +1,780 @@
+use std::{borrow::Borrow, fmt, ops::Bound, ptr::NonNull};
+
+use crate::{
+    monoid::Monoid,
+    node::{after_start, before_end, Node, Side},
+    priority::{DefaultPriority, PrioritySource},
+};
+
+use super::TreapMap;
+
+/// A cursor over a [`TreapMap`], pointing either at an entry or at the
+/// "ghost" non-entry that sits between the last entry and the first one.
+///
+/// The cursor remembers the path down to its entry, so stepping to a
+/// neighbour only walks the nodes in between, which takes amortized O(1).
+///
+/// Constructed by [`TreapMap::lower_bound`] and [`TreapMap::upper_bound`].
+pub struct Cursor<'a, K, V, M: Monoid<V> = ()> {
+    pos: Position<&'a Node<K, V, M>>,
+}
+
+/// A cursor over a [`TreapMap`] that can also change the values it passes,
+/// and insert and remove entries around its position.
+///
+/// Constructed by [`TreapMap::lower_bound_mut`] and
+/// [`TreapMap::upper_bound_mut`].
+pub struct CursorMut<'a, K, V, P = DefaultPriority> {
+    map: &'a mut TreapMap<K, V, P>,
+    pos: Position<NonNull<Node<K, V>>>,
+}
+
+impl<K: Ord, V, P, M: Monoid<V>> TreapMap<K, V, P, M> {
+    /// Returns a cursor pointing at the first entry above `bound`, or at the
+    /// ghost if there is none.
+    ///
+    /// Passing `Bound::Unbounded` points the cursor at the first entry.
+    pub fn lower_bound<Q>(&self, bound: Bound<&Q>) -> Cursor<'_, K, V, M>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut cursor = Cursor {
+            pos: Position::ghost(self.root.as_deref()),
+        };
+        cursor.seek_lower(bound);
+
+        cursor
+    }
+
+    /// Returns a cursor pointing at the last entry below `bound`, or at the
+    /// ghost if there is none.
+    ///
+    /// Passing `Bound::Unbounded` points the cursor at the last entry.
+    pub fn upper_bound<Q>(&self, bound: Bound<&Q>) -> Cursor<'_, K, V, M>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut cursor = Cursor {
+            pos: Position::ghost(self.root.as_deref()),
+        };
+        cursor.seek_upper(bound);
+
+        cursor
+    }
+}
+
+impl<K: Ord, V, P> TreapMap<K, V, P> {
+    /// Like [`TreapMap::lower_bound`], returning a [`CursorMut`].
+    pub fn lower_bound_mut<Q>(&mut self, bound: Bound<&Q>) -> CursorMut<'_, K, V, P>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut cursor = CursorMut::ghost(self);
+        cursor.seek_lower(bound);
+
+        cursor
+    }
+
+    /// Like [`TreapMap::upper_bound`], returning a [`CursorMut`].
+    pub fn upper_bound_mut<Q>(&mut self, bound: Bound<&Q>) -> CursorMut<'_, K, V, P>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut cursor = CursorMut::ghost(self);
+        cursor.seek_upper(bound);
+
+        cursor
+    }
+}
+
+impl<'a, K, V, M: Monoid<V>> Cursor<'a, K, V, M> {
+    pub fn key(&self) -> Option<&'a K> {
+        self.pos.current.map(|node| node.key())
+    }
+
+    pub fn value(&self) -> Option<&'a V> {
+        self.pos.current.map(|node| node.val())
+    }
+
+    pub fn key_value(&self) -> Option<(&'a K, &'a V)> {
+        self.pos.current.map(|node| (node.key(), node.val()))
+    }
+
+    /// Moves to the first entry above `bound`, or to the ghost if there is
+    /// none, like a cursor fresh from [`TreapMap::lower_bound`].
+    pub fn seek_lower<Q>(&mut self, bound: Bound<&Q>)
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.pos
+            .seek(Side::Left, |node| after_start(node.key().borrow(), bound));
+    }
+
+    /// Moves to the last entry below `bound`, or to the ghost if there is
+    /// none, like a cursor fresh from [`TreapMap::upper_bound`].
+    pub fn seek_upper<Q>(&mut self, bound: Bound<&Q>)
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.pos
+            .seek(Side::Right, |node| before_end(node.key().borrow(), bound));
+    }
+
+    /// Moves to the next entry, or from the last one to the ghost, or from
+    /// the ghost to the first one.
+    pub fn move_next(&mut self) {
+        self.pos.step(Side::Right);
+    }
+
+    /// Moves to the previous entry, or from the first one to the ghost, or
+    /// from the ghost to the last one.
+    pub fn move_prev(&mut self) {
+        self.pos.step(Side::Left);
+    }
+
+    /// Returns the entry [`Cursor::move_next`] would move to.
+    pub fn peek_next(&self) -> Option<(&'a K, &'a V)> {
+        self.pos
+            .peek(Side::Right)
+            .map(|node| (node.key(), node.val()))
+    }
+
+    /// Returns the entry [`Cursor::move_prev`] would move to.
+    pub fn peek_prev(&self) -> Option<(&'a K, &'a V)> {
+        self.pos
+            .peek(Side::Left)
+            .map(|node| (node.key(), node.val()))
+    }
+}
+
+impl<K, V, M: Monoid<V>> Clone for Cursor<'_, K, V, M> {
+    fn clone(&self) -> Self {
+        Self {
+            pos: self.pos.clone(),
+        }
+    }
+}
+
+impl<'a, K: Ord, V, P> CursorMut<'a, K, V, P> {
+    fn ghost(map: &'a mut TreapMap<K, V, P>) -> Self {
+        let root = map.root.as_deref_mut().map(NonNull::from);
+
+        Self {
+            map,
+            pos: Position::ghost(root),
+        }
+    }
+
+    /// Moves to the first entry above `bound`, or to the ghost if there is
+    /// none, like a cursor fresh from [`TreapMap::lower_bound_mut`].
+    pub fn seek_lower<Q>(&mut self, bound: Bound<&Q>)
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        // SAFETY: the nodes are in the map, which we borrow mutably
+        self.pos.seek(Side::Left, |node| {
+            after_start(unsafe { node.as_ref() }.key().borrow(), bound)
+        });
+    }
+
+    /// Moves to the last entry below `bound`, or to the ghost if there is
+    /// none, like a cursor fresh from [`TreapMap::upper_bound_mut`].
+    pub fn seek_upper<Q>(&mut self, bound: Bound<&Q>)
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        // SAFETY: the nodes are in the map, which we borrow mutably
+        self.pos.seek(Side::Right, |node| {
+            before_end(unsafe { node.as_ref() }.key().borrow(), bound)
+        });
+    }
+
+    pub fn key(&self) -> Option<&K> {
+        // SAFETY: the node is in the map, which we borrow mutably
+        self.pos.current.map(|node| unsafe { node.as_ref() }.key())
+    }
+
+    pub fn value(&self) -> Option<&V> {
+        // SAFETY: the node is in the map, which we borrow mutably
+        self.pos.current.map(|node| unsafe { node.as_ref() }.val())
+    }
+
+    pub fn value_mut(&mut self) -> Option<&mut V> {
+        // SAFETY: the node is in the map, which we borrow mutably
+        self.pos
+            .current
+            .map(|mut node| unsafe { node.as_mut() }.val_mut())
+    }
+
+    pub fn key_value_mut(&mut self) -> Option<(&K, &mut V)> {
+        // SAFETY: the node is in the map, which we borrow mutably
+        self.pos
+            .current
+            .map(|mut node| unsafe { node.as_mut() }.key_val_mut())
+    }
+
+    /// Moves to the next entry, or from the last one to the ghost, or from
+    /// the ghost to the first one.
+    pub fn move_next(&mut self) {
+        self.pos.step(Side::Right);
+    }
+
+    /// Moves to the previous entry, or from the first one to the ghost, or
+    /// from the ghost to the last one.
+    pub fn move_prev(&mut self) {
+        self.pos.step(Side::Left);
+    }
+
+    /// Returns the entry [`CursorMut::move_next`] would move to.
+    pub fn peek_next(&mut self) -> Option<(&K, &mut V)> {
+        self.pos.peek(Side::Right).map(Self::entry)
+    }
+
+    /// Returns the entry [`CursorMut::move_prev`] would move to.
+    pub fn peek_prev(&mut self) -> Option<(&K, &mut V)> {
+        self.pos.peek(Side::Left).map(Self::entry)
+    }
+
+    /// Returns a read-only cursor pointing at the same entry, borrowing this
+    /// one.
+    pub fn as_cursor(&self) -> Cursor<'_, K, V> {
+        // SAFETY: the nodes are in the map, which we borrow mutably, and the
+        // shared references live no longer than our own borrow
+        let deref = |node: NonNull<Node<K, V>>| unsafe { node.as_ref() };
+
+        Cursor {
+            pos: Position {
+                root: self.pos.root.map(deref),
+                path: self
+                    .pos
+                    .path
+                    .iter()
+                    .map(|&(node, side)| (deref(node), side))
+                    .collect(),
+                current: self.pos.current.map(deref),
+            },
+        }
+    }
+
+    /// Inserts an entry right after the cursor, or at the front if the
+    /// cursor is at the ghost. The cursor keeps pointing at the same entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` doesn't sort strictly between the entry at the cursor
+    /// and the next one.
+    pub fn insert_after(&mut self, key: K, val: V)
+    where
+        P: PrioritySource,
+    {
+        assert!(
+            self.key().is_none_or(|cur| *cur < key),
+            "key inserted after the cursor is not greater than its key"
+        );
+        assert!(
+            self.pos
+                .peek(Side::Right)
+                // SAFETY: the node is in the map, which we borrow mutably
+                .is_none_or(|next| key < *unsafe { next.as_ref() }.key()),
+            "key inserted after the cursor is not less than the next key"
+        );
+
+        // from the new entry, the one before it is where we were, or the
+        // ghost if we inserted at the front
+        self.attach(Side::Right, key, val);
+        self.pos.step(Side::Left);
+    }
+
+    /// Inserts an entry right before the cursor, or at the back if the
+    /// cursor is at the ghost. The cursor keeps pointing at the same entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` doesn't sort strictly between the previous entry and
+    /// the entry at the cursor.
+    pub fn insert_before(&mut self, key: K, val: V)
+    where
+        P: PrioritySource,
+    {
+        assert!(
+            self.key().is_none_or(|cur| key < *cur),
+            "key inserted before the cursor is not less than its key"
+        );
+        assert!(
+            self.pos
+                .peek(Side::Left)
+                // SAFETY: the node is in the map, which we borrow mutably
+                .is_none_or(|prev| *unsafe { prev.as_ref() }.key() < key),
+            "key inserted before the cursor is not greater than the previous key"
+        );
+
+        self.attach(Side::Left, key, val);
+        self.pos.step(Side::Right);
+    }
+
+    /// Removes the entry at the cursor and moves to the next one, or does
+    /// nothing at the ghost.
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        self.pos.current?;
+
+        // SAFETY: the path leads from the root down to the current node and
+        // the tree hasn't changed since it was recorded
+        let (key, val) = unsafe { Node::detach(&mut self.map.root, &self.pos.path) }?;
+        self.map.len -= 1;
+        self.map.debug_validate();
+
+        // a fresh borrow of the root would invalidate the path, which still
+        // starts at the root unless that is what was removed
+        if self.pos.path.is_empty() {
+            self.pos.root = self.map.root.as_deref_mut().map(NonNull::from);
+        }
+
+        // the nodes above the removed entry are untouched, and its slot now
+        // holds the rest of its subtree, where the next entry is unless it
+        // is one of those above
+        let below = match self.pos.path.last() {
+            Some(&(parent, side)) => parent.step(side),
+            None => self.pos.root,
+        };
+        // SAFETY: the nodes are in the map, which we borrow mutably
+        self.pos.seek_below(below, Side::Left, |node| {
+            after_start(unsafe { node.as_ref() }.key(), Bound::Excluded(&key))
+        });
+
+        Some((key, val))
+    }
+
+    /// Hangs a new entry in the gap on `side` of the cursor and points the
+    /// cursor at it.
+    ///
+    /// Rotations only swap entries between the nodes on the path down to the
+    /// gap, so the path to the new entry is the part of it above the node
+    /// the entry ends up in.
+    fn attach(&mut self, side: Side, key: K, val: V)
+    where
+        P: PrioritySource,
+    {
+        let mut path = self.pos.path.clone();
+        let mut node = match self.pos.current {
+            Some(current) => {
+                path.push((current, side));
+                current.step(side)
+            }
+            // at the ghost, the gap is at the other end of the map
+            None => self.pos.root,
+        };
+        while let Some(n) = node {
+            path.push((n, side.opposite()));
+            node = n.step(side.opposite());
+        }
+
+        let leaf = Node::boxed(key, val, self.map.priorities.next_priority());
+        self.map.len += 1;
+
+        // SAFETY: the path was recorded on the map, which we borrow mutably,
+        // and ends in an empty slot
+        let at = unsafe { Node::attach(&mut self.map.root, &path, leaf) };
+        self.map.debug_validate();
+
+        if let Some(above) = path.iter().position(|&(node, _)| node == at) {
+            path.truncate(above);
+        }
+        self.pos = Position {
+            // the top of the path, or the new entry once it is on top
+            root: path.first().map_or(Some(at), |&(root, _)| Some(root)),
+            path,
+            current: Some(at),
+        };
+    }
+
+    fn entry<'b>(mut node: NonNull<Node<K, V>>) -> (&'b K, &'b mut V) {
+        // SAFETY: the node is in the map, which the caller borrows mutably
+        // for `'b`
+        unsafe { node.as_mut() }.key_val_mut()
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, M: Monoid<V>> fmt::Debug for Cursor<'_, K, V, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Cursor").field(&self.key_value()).finish()
+    }
+}
+
+impl<K: Ord + fmt::Debug, V: fmt::Debug, P> fmt::Debug for CursorMut<'_, K, V, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CursorMut")
+            .field(&self.as_cursor().key_value())
+            .finish()
+    }
+}
+
+/// A way to step from a node down to one of its children, for both shared
+/// and exclusive borrows of the tree.
+trait Handle: Copy {
+    fn step(self, side: Side) -> Option<Self>;
+}
+
+impl<K, V, M: Monoid<V>> Handle for &Node<K, V, M> {
+    fn step(self, side: Side) -> Option<Self> {
+        self.child(side).as_deref()
+    }
+}
+
+impl<K, V> Handle for NonNull<Node<K, V>> {
+    fn step(mut self, side: Side) -> Option<Self> {
+        // SAFETY: these handles only live in a `CursorMut`, which borrows
+        // the map mutably, and are derived from that borrow
+        unsafe { self.as_mut() }
+            .child_mut(side)
+            .as_deref_mut()
+            .map(NonNull::from)
+    }
+}
+
+/// Where a cursor is: the nodes above it, each with the side the path goes
+/// on from there, and the node itself, or `None` at the ghost.
+struct Position<H> {
+    root: Option<H>,
+    path: Vec<(H, Side)>,
+    current: Option<H>,
+}
+
+impl<H: Handle> Position<H> {
+    fn ghost(root: Option<H>) -> Self {
+        Self {
+            root,
+            path: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// Points at the outermost node on `side` of the subtree under `node`.
+    fn descend(&mut self, mut node: H, side: Side) {
+        while let Some(child) = node.step(side) {
+            self.path.push((node, side));
+            node = child;
+        }
+
+        self.current = Some(node);
+    }
+
+    /// Moves to the neighbouring entry on `side`.
+    fn step(&mut self, side: Side) {
+        let Some(node) = self.current else {
+            if let Some(root) = self.root {
+                self.descend(root, side.opposite());
+            }
+            return;
+        };
+
+        if let Some(child) = node.step(side) {
+            self.path.push((node, side));
+            self.descend(child, side.opposite());
+            return;
+        }
+
+        // climb until we come up from the opposite side, at the ghost if we
+        // never do
+        self.current = None;
+        while let Some((parent, from)) = self.path.pop() {
+            if from == side.opposite() {
+                self.current = Some(parent);
+                break;
+            }
+        }
+    }
+
+    /// Returns the node [`Position::step`] would move to.
+    fn peek(&self, side: Side) -> Option<H> {
+        let outermost = |mut node: H| {
+            while let Some(child) = node.step(side.opposite()) {
+                node = child;
+            }
+            node
+        };
+
+        let Some(node) = self.current else {
+            return self.root.map(outermost);
+        };
+
+        match node.step(side) {
+            Some(child) => Some(outermost(child)),
+            None => self
+                .path
+                .iter()
+                .rev()
+                .find(|&&(_, from)| from == side.opposite())
+                .map(|&(parent, _)| parent),
+        }
+    }
+
+    /// Points at the outermost node on `side` among those that are `inside`,
+    /// which have to be the ones on that side of some key.
+    fn seek<F>(&mut self, side: Side, inside: F)
+    where
+        F: FnMut(H) -> bool,
+    {
+        self.path.clear();
+        self.seek_below(self.root, side, inside);
+    }
+
+    /// Like [`Position::seek`], searching only the subtree under `node`,
+    /// which hangs at the end of the path, and falling back on the closest
+    /// node above it that is `inside`.
+    fn seek_below<F>(&mut self, mut node: Option<H>, side: Side, mut inside: F)
+    where
+        F: FnMut(H) -> bool,
+    {
+        self.current = None;
+
+        let base = self.path.len();
+        let mut found = None;
+        while let Some(n) = node {
+            let next = if inside(n) {
+                self.current = Some(n);
+                found = Some(self.path.len());
+                side
+            } else {
+                side.opposite()
+            };
+
+            self.path.push((n, next));
+            node = n.step(next);
+        }
+
+        if let Some(found) = found {
+            self.path.truncate(found);
+            return;
+        }
+
+        // the path only turned toward `side` at nodes that are inside
+        self.path.truncate(base);
+        while let Some((parent, from)) = self.path.pop() {
+            if from == side {
+                self.current = Some(parent);
+                break;
+            }
+        }
+    }
+}
+
+impl<H: Copy> Clone for Position<H> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root,
+            path: self.path.clone(),
+            current: self.current,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build<K: Ord, V>(entries: impl IntoIterator<Item = (K, V)>) -> TreapMap<K, V> {
+        let mut map = TreapMap::new();
+        for (key, val) in entries {
+            map.insert(key, val);
+        }
+        map
+    }
+
+    #[test]
+    fn seek_and_move() {
+        let map = build((0..50).map(|i| (i * 2, i)));
+
+        let mut cursor = map.lower_bound(Bound::Included(&10));
+        assert_eq!(cursor.key(), Some(&10));
+        assert_eq!(cursor.peek_prev(), Some((&8, &4)));
+        assert_eq!(cursor.peek_next(), Some((&12, &6)));
+
+        cursor.move_next();
+        assert_eq!(cursor.key(), Some(&12));
+
+        let cursor = map.lower_bound(Bound::Excluded(&10));
+        assert_eq!(cursor.key(), Some(&12));
+        let cursor = map.upper_bound(Bound::Included(&11));
+        assert_eq!(cursor.key(), Some(&10));
+        let cursor = map.upper_bound(Bound::Excluded(&10));
+        assert_eq!(cursor.key(), Some(&8));
+        let cursor = map.lower_bound(Bound::Excluded(&98));
+        assert_eq!(cursor.key(), None);
+
+        let mut cursor = map.lower_bound::<i32>(Bound::Unbounded);
+        let mut forward = Vec::new();
+        while let Some(&key) = cursor.key() {
+            forward.push(key);
+            cursor.move_next();
+        }
+        assert!(forward.into_iter().eq((0..100).step_by(2)));
+
+        // the ghost sits between the last entry and the first one
+        assert_eq!(cursor.peek_next(), Some((&0, &0)));
+        assert_eq!(cursor.peek_prev(), Some((&98, &49)));
+        cursor.move_prev();
+
+        let mut backward = Vec::new();
+        while let Some(&key) = cursor.key() {
+            backward.push(key);
+            cursor.move_prev();
+        }
+        assert!(backward.into_iter().eq((0..100).step_by(2).rev()));
+    }
+
+    #[test]
+    fn lockstep_merge_join() {
+        let left = build((0..300).filter(|i| i % 2 == 0).map(|i| (i, ())));
+        let mut right = build((0..300).filter(|i| i % 3 == 0).map(|i| (i, i)));
+
+        // remove from `right` every key that is also in `left`
+        let mut a = left.lower_bound::<i32>(Bound::Unbounded);
+        let mut b = right.lower_bound_mut::<i32>(Bound::Unbounded);
+        while let (Some(x), Some(&y)) = (a.key(), b.key()) {
+            match x.cmp(&y) {
+                std::cmp::Ordering::Less => a.move_next(),
+                std::cmp::Ordering::Greater => b.move_next(),
+                std::cmp::Ordering::Equal => {
+                    assert_eq!(b.remove_current(), Some((y, y)));
+                    a.move_next();
+                }
+            }
+        }
+
+        assert!(right
+            .keys()
+            .copied()
+            .eq((0..300).filter(|i| i % 3 == 0 && i % 2 != 0)));
+    }
+
+    #[test]
+    fn insert_around_cursor() {
+        let mut map = build((0..20).map(|i| (i * 10, i)));
+
+        let mut cursor = map.lower_bound_mut(Bound::Included(&50));
+        for i in 1..10 {
+            cursor.insert_before(40 + i, 0);
+            cursor.insert_after(60 - i, 0);
+            assert_eq!(cursor.key(), Some(&50));
+        }
+        *cursor.value_mut().unwrap() = 500;
+        assert_eq!(cursor.peek_prev().map(|(k, _)| *k), Some(49));
+        assert_eq!(cursor.peek_next().map(|(k, _)| *k), Some(51));
+
+        cursor.move_next();
+        cursor.move_next();
+        assert_eq!(cursor.key(), Some(&52));
+        assert_eq!(cursor.remove_current(), Some((52, 0)));
+        assert_eq!(cursor.key(), Some(&53));
+
+        let mut cursor = map.upper_bound_mut::<i32>(Bound::Unbounded);
+        cursor.move_next();
+        cursor.insert_after(-1, 0);
+        cursor.insert_before(1000, 0);
+        assert_eq!(cursor.key(), None);
+
+        assert_eq!(map.len(), 20 + 18 - 1 + 2);
+        assert_eq!(map.get(&50), Some(&500));
+        assert_eq!(map.keys().next(), Some(&-1));
+        assert_eq!(map.keys().next_back(), Some(&1000));
+        assert!(map.keys().zip(map.keys().skip(1)).all(|(a, b)| a < b));
+    }
+
+    #[test]
+    fn edits_match_btree_map() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+        use std::collections::BTreeMap;
+
+        let mut rng = StdRng::seed_from_u64(16);
+        let mut map = TreapMap::with_seed(16);
+        let mut reference = BTreeMap::new();
+        for i in 0..200 {
+            map.insert(i * 1000, i);
+            reference.insert(i * 1000, i);
+        }
+
+        let mut cursor = map.lower_bound_mut(Bound::Included(&100_000));
+        for step in 0..5000 {
+            let key = cursor.key().copied();
+            let prev = cursor.peek_prev().map(|(k, _)| *k);
+            let next = cursor.peek_next().map(|(k, _)| *k);
+
+            match (rng.gen_range(0..5), prev, key, next) {
+                (0, ..) => cursor.move_next(),
+                (1, ..) => cursor.move_prev(),
+                (2, _, Some(key), Some(next)) if next - key > 1 => {
+                    cursor.insert_after((key + next) / 2, step);
+                    reference.insert((key + next) / 2, step);
+                }
+                (3, Some(prev), Some(key), _) if key - prev > 1 => {
+                    cursor.insert_before((prev + key) / 2, step);
+                    reference.insert((prev + key) / 2, step);
+                }
+                (4, _, Some(key), _) => {
+                    assert_eq!(cursor.remove_current(), reference.remove_entry(&key));
+                }
+                _ => {}
+            }
+
+            let key = cursor.key().copied();
+            let (prev, next) = match key {
+                Some(key) => (
+                    reference.range(..key).next_back(),
+                    reference.range(key + 1..).next(),
+                ),
+                None => (reference.iter().next_back(), reference.iter().next()),
+            };
+            assert_eq!(cursor.peek_prev().map(|(k, v)| (k, &*v)), prev);
+            assert_eq!(cursor.peek_next().map(|(k, v)| (k, &*v)), next);
+        }
+
+        assert_eq!(map.len(), reference.len());
+        assert!(map.iter().eq(reference.iter()));
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn seek_again() {
+        let left = build((0..100).filter(|i| i % 7 == 0).map(|i| (i, ())));
+        let right = build((0..100).filter(|i| i % 5 == 0).map(|i| (i, ())));
+
+        // leapfrog intersection: each cursor seeks past the other's key
+        let mut a = left.lower_bound::<i32>(Bound::Unbounded);
+        let mut b = right.lower_bound::<i32>(Bound::Unbounded);
+        let mut common = Vec::new();
+        while let (Some(&x), Some(&y)) = (a.key(), b.key()) {
+            if x == y {
+                common.push(x);
+                a.move_next();
+            } else if x < y {
+                a.seek_lower(Bound::Included(&y));
+            } else {
+                b.seek_lower(Bound::Included(&x));
+            }
+        }
+        assert_eq!(common, [0, 35, 70]);
+
+        let mut map = build((0..10).map(|i| (i, i)));
+        let mut cursor = map.lower_bound_mut::<i32>(Bound::Unbounded);
+        cursor.seek_upper(Bound::Excluded(&5));
+        assert_eq!(cursor.key(), Some(&4));
+        cursor.seek_lower(Bound::Excluded(&9));
+        assert_eq!(cursor.key(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_out_of_order() {
+        let mut map = build((0..10).map(|i| (i, i)));
+        let mut cursor = map.lower_bound_mut(Bound::Included(&5));
+        cursor.insert_after(7, 0);
+    }
+}
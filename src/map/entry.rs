@@ -0,0 +1,252 @@
+use std::{fmt, ptr::NonNull};
+
+use crate::{
+    node::{Node, Path},
+    priority::{DefaultPriority, PrioritySource},
+};
+
+use super::TreapMap;
+
+/// A view into a single entry of a [`TreapMap`], which may either be vacant
+/// or occupied.
+///
+/// Constructed by [`TreapMap::entry`].
+pub enum Entry<'a, K, V, P = DefaultPriority> {
+    Vacant(VacantEntry<'a, K, V, P>),
+    Occupied(OccupiedEntry<'a, K, V, P>),
+}
+
+/// A vacant entry, remembering the path down to where its key belongs.
+pub struct VacantEntry<'a, K, V, P = DefaultPriority> {
+    map: &'a mut TreapMap<K, V, P>,
+    path: Path<K, V>,
+    key: K,
+}
+
+/// An occupied entry, remembering the path down to its node.
+pub struct OccupiedEntry<'a, K, V, P = DefaultPriority> {
+    map: &'a mut TreapMap<K, V, P>,
+    path: Path<K, V>,
+    node: NonNull<Node<K, V>>,
+}
+
+impl<K: Ord, V, P> TreapMap<K, V, P> {
+    /// Gets the entry for `key` for in-place manipulation, walking the tree
+    /// only once.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, P> {
+        let (path, found) = Node::search(&mut self.root, &key);
+
+        match found {
+            Some(node) => Entry::Occupied(OccupiedEntry {
+                map: self,
+                path,
+                node,
+            }),
+            None => Entry::Vacant(VacantEntry {
+                map: self,
+                path,
+                key,
+            }),
+        }
+    }
+}
+
+impl<'a, K: Ord, V, P: PrioritySource> Entry<'a, K, V, P> {
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Vacant(entry) => entry.insert(default),
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Vacant(entry) => entry.insert(default()),
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Vacant(entry) => {
+                let val = default(&entry.key);
+                entry.insert(val)
+            }
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+
+        self
+    }
+
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Vacant(entry) => entry.key(),
+            Entry::Occupied(entry) => entry.key(),
+        }
+    }
+}
+
+impl<'a, K: Ord, V, P: PrioritySource> VacantEntry<'a, K, V, P> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts the value, restoring the heap invariant with the same
+    /// rotations [`TreapMap::insert`] would do.
+    pub fn insert(self, val: V) -> &'a mut V {
+        let map = self.map;
+        let leaf = Node::boxed(self.key, val, map.priorities.next_priority());
+        map.len += 1;
+
+        // SAFETY: the path was recorded by `TreapMap::entry` and the map
+        // has been mutably borrowed ever since
+        let mut node = unsafe { Node::attach(&mut map.root, &self.path, leaf) };
+        map.debug_validate();
+
+        // SAFETY: the node lives in the map, which we borrow for `'a`
+        unsafe { node.as_mut() }.val_mut()
+    }
+}
+
+impl<'a, K: Ord, V, P> OccupiedEntry<'a, K, V, P> {
+    pub fn key(&self) -> &K {
+        // SAFETY: the node lives in the map we borrow
+        unsafe { self.node.as_ref() }.key()
+    }
+
+    pub fn get(&self) -> &V {
+        // SAFETY: the node lives in the map we borrow
+        unsafe { self.node.as_ref() }.val()
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        // SAFETY: the node lives in the map we borrow mutably
+        unsafe { self.node.as_mut() }.val_mut()
+    }
+
+    pub fn into_mut(mut self) -> &'a mut V {
+        // SAFETY: the node lives in the map, which we borrow for `'a`
+        unsafe { self.node.as_mut() }.val_mut()
+    }
+
+    pub fn insert(&mut self, val: V) -> V {
+        std::mem::replace(self.get_mut(), val)
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        let map = self.map;
+        map.len -= 1;
+
+        // SAFETY: the path was recorded by `TreapMap::entry` and the map
+        // has been mutably borrowed ever since
+        let entry = unsafe { Node::detach(&mut map.root, &self.path) };
+        map.debug_validate();
+
+        entry.expect("occupied entry points at a node")
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, P> fmt::Debug for Entry<'_, K, V, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Vacant(entry) => f.debug_tuple("Entry").field(entry).finish(),
+            Entry::Occupied(entry) => f.debug_tuple("Entry").field(entry).finish(),
+        }
+    }
+}
+
+impl<K: fmt::Debug, V, P> fmt::Debug for VacantEntry<'_, K, V, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VacantEntry").field(&self.key).finish()
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, P> fmt::Debug for OccupiedEntry<'_, K, V, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // SAFETY: the node lives in the map we borrow
+        let node = unsafe { self.node.as_ref() };
+
+        f.debug_struct("OccupiedEntry")
+            .field("key", node.key())
+            .field("value", node.val())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter() {
+        let mut map = TreapMap::new();
+        let words = "a b c a b a d e a".split(' ');
+
+        for word in words {
+            *map.entry(word).or_insert(0) += 1;
+        }
+
+        let counts: Vec<_> = map.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(
+            counts,
+            vec![("a", 4), ("b", 2), ("c", 1), ("d", 1), ("e", 1)]
+        );
+    }
+
+    #[test]
+    fn vacant_insert_keeps_order() {
+        let mut map = TreapMap::new();
+
+        for i in (0..200).rev().step_by(3) {
+            map.entry(i).or_insert_with_key(|k| k * 10);
+        }
+        for i in 0..200 {
+            map.entry(i).and_modify(|v| *v += 1).or_default();
+        }
+
+        assert_eq!(map.len(), 200);
+        for (&k, &v) in &map {
+            let expected = if (199 - k) % 3 == 0 { k * 10 + 1 } else { 0 };
+            assert_eq!(v, expected);
+        }
+    }
+
+    #[test]
+    fn occupied_remove() {
+        let mut map = TreapMap::new();
+        for i in 0..50 {
+            map.insert(i, i);
+        }
+
+        for i in (0..50).step_by(2) {
+            match map.entry(i) {
+                Entry::Occupied(entry) => assert_eq!(entry.remove_entry(), (i, i)),
+                Entry::Vacant(_) => panic!("{} should be occupied", i),
+            }
+        }
+
+        assert_eq!(map.len(), 25);
+        assert!(map.keys().copied().eq((1..50).step_by(2)));
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    borrow::Borrow,
+    num::NonZeroU32,
+    sync::atomic::{AtomicU32, Ordering::Relaxed},
+};
+
+use crate::{monoid::Monoid, node::Node, priority::PrioritySource};
+
+use super::TreapMap;
+
+/// The stamp of the next entry to be given a handle, shared by every map
+/// so that no two entries get the same one.
+static NEXT_STAMP: AtomicU32 = AtomicU32::new(1);
+
+/// Points at an entry of a [`TreapMap`] without borrowing the map.
+///
+/// Rotations move entries from node to node, so a handle can't point at a
+/// node. It keeps a copy of the key instead, along with a stamp that tells
+/// the entry apart from any other that has had the same key, and is looked
+/// up again in O(log n).
+///
+/// A handle stays valid for as long as its entry is in the map, whatever
+/// else gets inserted or removed, and when [`TreapMap::insert`] replaces
+/// its value. Once the entry is removed the handle goes stale, and the
+/// `*_by_handle` methods return `None` for it, even after the key is
+/// inserted again. Entries moved to another map by [`TreapMap::split_off`]
+/// or [`TreapMap::append`] take their handles with them, except when
+/// `append` has to pick between two entries with the same key.
+///
+/// Stamps are 32 bits wide and shared by every map, so a handle kept across
+/// more than four billion others being given out may be mistaken for a
+/// live one. [`ArenaTreapMap`](crate::arena::ArenaTreapMap) has handles
+/// that are `Copy` and resolve in O(1).
+///
+/// ```
+/// use treap::map::TreapMap;
+///
+/// let mut map = TreapMap::new();
+/// let (a, _) = map.insert_with_handle("a", 1);
+/// map.insert("b", 2);
+///
+/// *map.get_by_handle_mut(&a).unwrap().1 += 10;
+/// assert_eq!(map.get(&"a"), Some(&11));
+///
+/// assert_eq!(map.remove_by_handle(&a), Some(("a", 11)));
+/// map.insert("a", 1);
+/// assert_eq!(map.get_by_handle(&a), None);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Handle<K> {
+    key: K,
+    stamp: NonZeroU32,
+}
+
+impl<K> Handle<K> {
+    /// Returns the key of the entry, whether or not it is still in the map.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: Ord + Clone, V, P, M: Monoid<V>> TreapMap<K, V, P, M> {
+    /// Like [`TreapMap::insert`], also returning a handle to the entry.
+    ///
+    /// If the key was already present, the entry keeps its handle.
+    pub fn insert_with_handle(&mut self, key: K, val: V) -> (Handle<K>, Option<V>)
+    where
+        P: PrioritySource,
+    {
+        let handle_key = key.clone();
+        let old_val = self.insert(key, val);
+        let handle = self
+            .handle(&handle_key)
+            .expect("the entry was just inserted");
+
+        (handle, old_val)
+    }
+
+    /// Returns a handle to the entry for `key`.
+    ///
+    /// Borrows the map mutably to stamp the entry the first time a handle
+    /// is taken to it.
+    pub fn handle<Q>(&mut self, key: &Q) -> Option<Handle<K>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let node = Node::find_mut(&mut self.root, key)?;
+
+        let stamp = match NonZeroU32::new(node.stamp()) {
+            Some(stamp) => stamp,
+            None => {
+                let stamp = next_stamp();
+                node.set_stamp(stamp.get());
+                stamp
+            }
+        };
+
+        Some(Handle {
+            key: node.key().clone(),
+            stamp,
+        })
+    }
+}
+
+impl<K: Ord, V, P, M: Monoid<V>> TreapMap<K, V, P, M> {
+    /// Returns the entry behind `handle`, or `None` if it was removed.
+    pub fn get_by_handle(&self, handle: &Handle<K>) -> Option<(&K, &V)> {
+        self.root
+            .as_ref()
+            .and_then(|root| root.find(&handle.key))
+            .filter(|node| node.stamp() == handle.stamp.get())
+            .map(|node| (node.key(), node.val()))
+    }
+
+    /// Removes the entry behind `handle`, or returns `None` if it was already
+    /// removed.
+    pub fn remove_by_handle(&mut self, handle: &Handle<K>) -> Option<(K, V)> {
+        if !self.contains_handle(handle) {
+            return None;
+        }
+
+        self.remove_entry(&handle.key)
+    }
+
+    /// Returns whether the entry behind `handle` is still in the map.
+    pub fn contains_handle(&self, handle: &Handle<K>) -> bool {
+        self.get_by_handle(handle).is_some()
+    }
+}
+
+impl<K: Ord, V, P> TreapMap<K, V, P> {
+    /// Like [`TreapMap::get_by_handle`], with a mutable reference to the
+    /// value.
+    pub fn get_by_handle_mut(&mut self, handle: &Handle<K>) -> Option<(&K, &mut V)> {
+        Node::find_mut(&mut self.root, &handle.key)
+            .filter(|node| node.stamp() == handle.stamp.get())
+            .map(Node::key_val_mut)
+    }
+}
+
+fn next_stamp() -> NonZeroU32 {
+    loop {
+        // zero marks entries without handles, and is skipped on wrapping
+        if let Some(stamp) = NonZeroU32::new(NEXT_STAMP.fetch_add(1, Relaxed)) {
+            return stamp;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::TreapMap;
+
+    #[test]
+    fn handles_survive_other_writes() {
+        let mut map = TreapMap::with_seed(20);
+        let handles: Vec<_> = (0..500)
+            .map(|i| map.insert_with_handle(i * 2, i).0)
+            .collect();
+
+        // rotations move entries between nodes all the while
+        for i in 0..500 {
+            map.insert(i * 2 + 1, i);
+        }
+        for i in (0..500).step_by(3) {
+            map.remove(&(i * 2 + 1));
+        }
+        assert_eq!(map.insert(10, 0), Some(5));
+
+        for (i, handle) in handles.iter().enumerate() {
+            let val = if i == 5 { 0 } else { i };
+            assert_eq!(map.get_by_handle(handle), Some((&(i * 2), &val)));
+        }
+
+        *map.get_by_handle_mut(&handles[7]).unwrap().1 = 70;
+        assert_eq!(map.get(&14), Some(&70));
+    }
+
+    #[test]
+    fn stale_handles() {
+        let mut map = TreapMap::new();
+        let (a, _) = map.insert_with_handle(1, "a");
+        let b = map.handle(&1).unwrap();
+        assert_eq!(a, b);
+
+        assert_eq!(map.remove_by_handle(&a), Some((1, "a")));
+        assert_eq!(map.remove_by_handle(&a), None);
+
+        // the key comes back as a different entry
+        map.insert(1, "b");
+        assert!(!map.contains_handle(&a));
+        assert_eq!(map.get_by_handle_mut(&a), None);
+        assert_ne!(map.handle(&1), Some(a));
+        assert_eq!(map.handle(&2), None);
+    }
+
+    #[test]
+    fn handles_follow_moved_entries() {
+        let mut map = TreapMap::new();
+        let handles: Vec<_> = (0..10).map(|i| map.insert_with_handle(i, i).0).collect();
+
+        let mut right = map.split_off(&5);
+        assert!(map.contains_handle(&handles[4]));
+        assert!(!map.contains_handle(&handles[5]));
+        assert!(right.contains_handle(&handles[5]));
+
+        // a handle from one map doesn't match another entry with its key
+        let mut other = TreapMap::new();
+        other.insert(5, 5);
+        other.handle(&5);
+        assert!(!other.contains_handle(&handles[5]));
+
+        map.append(&mut right);
+        assert!(handles.iter().all(|handle| map.contains_handle(handle)));
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt::Write;
+
+use crate::{monoid::Monoid, node::Node};
+
+use super::TreapMap;
+
+impl<K, V, P, M: Monoid<V>> TreapMap<K, V, P, M> {
+    /// Renders the tree as a Graphviz DOT digraph, labelling every node with
+    /// its key, value and priority, and every edge with the side it hangs
+    /// on.
+    ///
+    /// Only the top `max_depth` levels are drawn, each subtree below them is
+    /// collapsed into a single node giving its size.
+    ///
+    /// ```
+    /// use treap::map::TreapMap;
+    ///
+    /// let mut map = TreapMap::new();
+    /// map.insert(1, "a");
+    ///
+    /// let dot = map.to_dot(8, |k| k.to_string(), |v| v.to_string());
+    /// assert!(dot.starts_with("digraph treap {"));
+    /// ```
+    pub fn to_dot<FK, FV>(&self, max_depth: usize, fmt_key: FK, fmt_val: FV) -> String
+    where
+        FK: Fn(&K) -> String,
+        FV: Fn(&V) -> String,
+    {
+        let mut out = String::from("digraph treap {\n    node [shape=box];\n");
+        let mut ids = 0..;
+        let mut stack: Vec<_> = self
+            .root
+            .as_deref()
+            .map(|root| (root, 0, ids.next().unwrap_or_default()))
+            .into_iter()
+            .collect();
+
+        while let Some((node, depth, id)) = stack.pop() {
+            if depth == max_depth {
+                let size = node.subtree_size();
+                let _ = writeln!(
+                    out,
+                    "    n{id} [label=\"... {size} more\", shape=plaintext];"
+                );
+                continue;
+            }
+
+            let _ = writeln!(
+                out,
+                "    n{id} [label=\"{}: {}\\npriority {}\"];",
+                escape(&fmt_key(node.key())),
+                escape(&fmt_val(node.val())),
+                node.priority(),
+            );
+
+            for (child, side) in [(node.left(), "L"), (node.right(), "R")] {
+                let child_id = ids.next().unwrap_or_default();
+                match child {
+                    Some(child) => {
+                        let _ = writeln!(out, "    n{id} -> n{child_id} [label=\"{side}\"];");
+                        stack.push((child, depth + 1, child_id));
+                    }
+                    // an invisible stand-in keeps a lone child on its side
+                    None => {
+                        let _ = writeln!(out, "    n{child_id} [style=invis];");
+                        let _ = writeln!(out, "    n{id} -> n{child_id} [style=invis];");
+                    }
+                }
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    /// Draws the tree sideways, one entry per line, with the root on the
+    /// left, right subtrees above their parents and left subtrees below, so
+    /// that keys read in descending order from top to bottom.
+    ///
+    /// Only the top `max_depth` levels are drawn, each subtree below them is
+    /// collapsed into a single line giving its size.
+    ///
+    /// ```
+    /// use treap::map::TreapMap;
+    ///
+    /// let mut map = TreapMap::new();
+    /// map.insert(1, "a");
+    ///
+    /// let tree = map.pretty_tree(8, |k| k.to_string(), |v| v.to_string());
+    /// assert!(tree.starts_with("1: a "));
+    /// ```
+    pub fn pretty_tree<FK, FV>(&self, max_depth: usize, fmt_key: FK, fmt_val: FV) -> String
+    where
+        FK: Fn(&K) -> String,
+        FV: Fn(&V) -> String,
+    {
+        enum Step<'a, K, V, M: Monoid<V>> {
+            Tree(&'a Node<K, V, M>, usize, String, Side),
+            Line(String),
+        }
+
+        #[derive(Clone, Copy, PartialEq)]
+        enum Side {
+            Root,
+            Left,
+            Right,
+        }
+
+        let mut out = String::new();
+        let mut stack: Vec<_> = self
+            .root
+            .as_deref()
+            .map(|root| Step::Tree(root, 0, String::new(), Side::Root))
+            .into_iter()
+            .collect();
+
+        while let Some(step) = stack.pop() {
+            let (node, depth, prefix, side) = match step {
+                Step::Line(line) => {
+                    out.push_str(&line);
+                    out.push('\n');
+                    continue;
+                }
+                Step::Tree(node, depth, prefix, side) => (node, depth, prefix, side),
+            };
+
+            let branch = match side {
+                Side::Root => "",
+                Side::Right => "┌── ",
+                Side::Left => "└── ",
+            };
+
+            if depth == max_depth {
+                let size = node.subtree_size();
+                let _ = writeln!(out, "{prefix}{branch}... {size} more");
+                continue;
+            }
+
+            // a vertical bar runs past us between a parent and the child on
+            // its far side
+            let inner = |toward| match side {
+                Side::Root => String::new(),
+                _ if side == toward => format!("{prefix}    "),
+                _ => format!("{prefix}│   "),
+            };
+
+            // pushed in reverse: the right subtree comes out first
+            if let Some(left) = node.left() {
+                stack.push(Step::Tree(left, depth + 1, inner(Side::Left), Side::Left));
+            }
+            stack.push(Step::Line(format!(
+                "{prefix}{branch}{}: {} ({})",
+                fmt_key(node.key()),
+                fmt_val(node.val()),
+                node.priority(),
+            )));
+            if let Some(right) = node.right() {
+                stack.push(Step::Tree(
+                    right,
+                    depth + 1,
+                    inner(Side::Right),
+                    Side::Right,
+                ));
+            }
+        }
+
+        out
+    }
+}
+
+/// Escapes a label for a double-quoted DOT string.
+fn escape(label: &str) -> String {
+    let mut out = String::with_capacity(label.len());
+
+    for c in label.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::priority::PrioritySource;
+
+    use super::*;
+
+    /// Hands out the given priorities in order, so that tests get a fixed
+    /// shape.
+    struct Fixed(Vec<u64>);
+
+    impl PrioritySource for Fixed {
+        fn next_priority(&mut self) -> u64 {
+            self.0.remove(0)
+        }
+    }
+
+    fn sample() -> TreapMap<u8, char, Fixed> {
+        //      d
+        //     / \
+        //    b   e
+        //   / \
+        //  a   c
+        let mut map = TreapMap::with_priorities(Fixed(vec![50, 40, 30, 20, 10]));
+        for key in [b'd', b'b', b'e', b'a', b'c'] {
+            map.insert(key, key.to_ascii_uppercase() as char);
+        }
+
+        map
+    }
+
+    #[test]
+    fn pretty_tree() {
+        let map = sample();
+        let tree = map.pretty_tree(usize::MAX, |k| (*k as char).to_string(), char::to_string);
+
+        assert_eq!(
+            tree,
+            "\
+┌── e: E (30)
+d: D (50)
+│   ┌── c: C (10)
+└── b: B (40)
+    └── a: A (20)
+"
+        );
+
+        let tree = map.pretty_tree(1, |k| (*k as char).to_string(), char::to_string);
+        assert_eq!(
+            tree,
+            "\
+┌── ... 1 more
+d: D (50)
+└── ... 3 more
+"
+        );
+    }
+
+    #[test]
+    fn to_dot() {
+        let map = sample();
+        let dot = map.to_dot(
+            usize::MAX,
+            |k| format!("\"{}\"", *k as char),
+            char::to_string,
+        );
+
+        assert!(dot.starts_with("digraph treap {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains(r#"[label="\"d\": D\npriority 50"];"#));
+        assert_eq!(dot.matches(" -> ").count(), 2 * map.len());
+        assert_eq!(dot.matches("[label=\"L\"]").count(), 2);
+        assert_eq!(dot.matches("[label=\"R\"]").count(), 2);
+
+        let dot = map.to_dot(1, |k| k.to_string(), char::to_string);
+        assert!(dot.contains("... 3 more"));
+        assert!(!dot.contains("priority 40"));
+    }
+}
@@ -0,0 +1,258 @@
+use std::{cmp::Ordering, fmt, marker::PhantomData};
+
+use ::serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    monoid::Monoid,
+    node::{Node, Spine},
+    priority::PrioritySource,
+};
+
+use super::TreapMap;
+
+/// Serializes the entries as a map, in key order.
+impl<K: Serialize, V: Serialize, P, M: Monoid<V>> Serialize for TreapMap<K, V, P, M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+/// Deserializes a map, building the tree in linear time for as long as the
+/// keys come in ascending order, and inserting them one by one after that.
+///
+/// A key that comes up twice is an error.
+impl<'de, K, V, P, M> Deserialize<'de> for TreapMap<K, V, P, M>
+where
+    K: Deserialize<'de> + Ord,
+    V: Deserialize<'de>,
+    P: PrioritySource + Default,
+    M: Monoid<V>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(MapVisitor(PhantomData))
+    }
+}
+
+struct MapVisitor<K, V, P, M>(PhantomData<(K, V, P, M)>);
+
+impl<'de, K, V, P, M> Visitor<'de> for MapVisitor<K, V, P, M>
+where
+    K: Deserialize<'de> + Ord,
+    V: Deserialize<'de>,
+    P: PrioritySource + Default,
+    M: Monoid<V>,
+{
+    type Value = TreapMap<K, V, P, M>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let mut map = TreapMap::<K, V, P, M>::default();
+        let mut spine = Spine::new();
+
+        let rest = loop {
+            let Some((key, val)) = access.next_entry::<K, V>()? else {
+                break None;
+            };
+
+            match spine.last_key().map(|last| key.cmp(last)) {
+                None | Some(Ordering::Greater) => {
+                    spine.push(key, val, map.priorities.next_priority());
+                }
+                Some(Ordering::Equal) => return Err(de::Error::custom("duplicate key in map")),
+                Some(Ordering::Less) => break Some((key, val)),
+            }
+        };
+
+        map.root = spine.finish();
+        map.len = Node::size(&map.root);
+
+        if let Some((key, val)) = rest {
+            if map.insert(key, val).is_some() {
+                return Err(de::Error::custom("duplicate key in map"));
+            }
+            while let Some((key, val)) = access.next_entry()? {
+                if map.insert(key, val).is_some() {
+                    return Err(de::Error::custom("duplicate key in map"));
+                }
+            }
+        }
+        map.debug_validate();
+
+        Ok(map)
+    }
+}
+
+/// Serializes a [`TreapMap`] along with the priority of every node, so that
+/// deserializing it restores the exact same tree.
+///
+/// Meant for `#[serde(with = "treap::map::serde_with_priorities")]`. The
+/// entries are written as a sequence of `(key, value, priority)` tuples in
+/// key order, and have to be read back in that order.
+///
+/// ```
+/// use treap::map::{serde_with_priorities, TreapMap};
+///
+/// let mut map: TreapMap<u32, char> = TreapMap::new();
+/// map.insert(1, 'a');
+/// map.insert(2, 'b');
+///
+/// let json = serde_with_priorities::serialize(&map, serde_json::value::Serializer).unwrap();
+/// let copy: TreapMap<u32, char> = serde_with_priorities::deserialize(json).unwrap();
+///
+/// assert_eq!(copy.pretty_tree(8, u32::to_string, char::to_string),
+///            map.pretty_tree(8, u32::to_string, char::to_string));
+/// ```
+pub mod serde_with_priorities {
+    use super::*;
+
+    pub fn serialize<K, V, P, M, S>(
+        map: &TreapMap<K, V, P, M>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        M: Monoid<V>,
+        S: Serializer,
+    {
+        // an in-order walk over the nodes rather than the entries, for the
+        // priorities
+        let mut stack = Vec::new();
+        let mut node = map.root.as_deref();
+        let nodes = std::iter::from_fn(move || {
+            while let Some(n) = node {
+                stack.push(n);
+                node = n.left();
+            }
+
+            let n = stack.pop()?;
+            node = n.right();
+
+            Some((n.key(), n.val(), n.priority()))
+        });
+
+        serializer.collect_seq(nodes)
+    }
+
+    pub fn deserialize<'de, K, V, P, M, D>(
+        deserializer: D,
+    ) -> Result<TreapMap<K, V, P, M>, D::Error>
+    where
+        K: Deserialize<'de> + Ord,
+        V: Deserialize<'de>,
+        P: Default,
+        M: Monoid<V>,
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(SeqVisitor(PhantomData))
+    }
+
+    struct SeqVisitor<K, V, P, M>(PhantomData<(K, V, P, M)>);
+
+    impl<'de, K, V, P, M> Visitor<'de> for SeqVisitor<K, V, P, M>
+    where
+        K: Deserialize<'de> + Ord,
+        V: Deserialize<'de>,
+        P: Default,
+        M: Monoid<V>,
+    {
+        type Value = TreapMap<K, V, P, M>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a sequence of (key, value, priority) tuples in key order")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+            let mut spine = Spine::new();
+
+            while let Some((key, val, priority)) = access.next_element::<(K, V, u64)>()? {
+                match spine.last_key().map(|last| key.cmp(last)) {
+                    None | Some(Ordering::Greater) => spine.push(key, val, priority),
+                    Some(Ordering::Equal) => {
+                        return Err(de::Error::custom("duplicate key in map"));
+                    }
+                    Some(Ordering::Less) => {
+                        return Err(de::Error::custom("keys are not in ascending order"));
+                    }
+                }
+            }
+
+            let mut map = TreapMap::default();
+            map.root = spine.finish();
+            map.len = Node::size(&map.root);
+            map.debug_validate();
+
+            Ok(map)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let mut map: TreapMap<u32, String> = TreapMap::new();
+        for i in [5, 3, 9, 1] {
+            map.insert(i, i.to_string());
+        }
+
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(json, r#"{"1":"1","3":"3","5":"5","9":"9"}"#);
+
+        let copy: TreapMap<u32, String> = serde_json::from_str(&json).unwrap();
+        assert!(copy.iter().eq(map.iter()));
+        assert_eq!(copy.validate(), Ok(()));
+    }
+
+    #[test]
+    fn unsorted_input() {
+        let map: TreapMap<u32, u32> =
+            serde_json::from_str(r#"{"1":1,"2":2,"7":7,"4":4,"3":3,"9":9}"#).unwrap();
+
+        assert!(map.keys().copied().eq([1, 2, 3, 4, 7, 9]));
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        for json in [r#"{"1":1,"1":2}"#, r#"{"1":1,"3":3,"2":2,"3":4}"#] {
+            let err = serde_json::from_str::<TreapMap<u32, u32>>(json).unwrap_err();
+            assert!(err.to_string().contains("duplicate key"), "{err}");
+        }
+    }
+
+    #[test]
+    fn priorities_restore_shape() {
+        let mut map: TreapMap<u32, u32> = TreapMap::new();
+        for i in 0..200 {
+            map.insert(i * 7 % 200, i);
+        }
+
+        let json = serde_with_priorities::serialize(&map, serde_json::value::Serializer).unwrap();
+        let copy: TreapMap<u32, u32> = serde_with_priorities::deserialize(json).unwrap();
+
+        let draw =
+            |map: &TreapMap<u32, u32>| map.pretty_tree(usize::MAX, u32::to_string, u32::to_string);
+        assert_eq!(draw(&copy), draw(&map));
+        assert_eq!(copy.validate(), Ok(()));
+
+        let unsorted = serde_json::json!([[2, 0, 10], [1, 0, 20]]);
+        let err = serde_with_priorities::deserialize::<
+            u32,
+            u32,
+            crate::priority::DefaultPriority,
+            (),
+            _,
+        >(unsorted)
+        .unwrap_err();
+        assert!(err.to_string().contains("ascending"), "{err}");
+    }
+}
@@ -0,0 +1,465 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+};
+
+use crate::{monoid::Monoid, node::Preorder};
+
+use super::{InvariantError, TreapMap};
+
+const MAGIC: [u8; 4] = *b"TRPS";
+const VERSION: u32 = 1;
+
+const HAS_LEFT: u8 = 1;
+const HAS_RIGHT: u8 = 2;
+
+/// Writes a value to a snapshot and reads it back, see
+/// [`TreapMap::write_snapshot`].
+///
+/// Integers are written little-endian at their full width, strings and
+/// vectors as a `u64` length followed by their contents.
+pub trait Codec: Sized {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self>;
+}
+
+macro_rules! int_codec {
+    ($($int:ty),*) => {$(
+        impl Codec for $int {
+            fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                writer.write_all(&self.to_le_bytes())
+            }
+
+            fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+                let mut bytes = [0; std::mem::size_of::<$int>()];
+                reader.read_exact(&mut bytes)?;
+                Ok(<$int>::from_le_bytes(bytes))
+            }
+        }
+    )*};
+}
+
+int_codec!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Codec for () {
+    fn encode<W: Write>(&self, _: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn decode<R: Read>(_: &mut R) -> io::Result<Self> {
+        Ok(())
+    }
+}
+
+impl Codec for bool {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        u8::from(*self).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        match u8::decode(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid bool")),
+        }
+    }
+}
+
+impl Codec for char {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        u32::from(*self).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        char::from_u32(u32::decode(reader)?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid char"))
+    }
+}
+
+impl Codec for String {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (self.len() as u64).encode(writer)?;
+        writer.write_all(self.as_bytes())
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let bytes = read_bytes(reader)?;
+        String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+impl<T: Codec> Codec for Vec<T> {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (self.len() as u64).encode(writer)?;
+        self.iter().try_for_each(|item| item.encode(writer))
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        // a corrupted length must not turn into a huge allocation, so the
+        // vector only grows as items actually come in
+        let len = u64::decode(reader)?;
+        let mut items = Vec::new();
+        for _ in 0..len {
+            items.push(T::decode(reader)?);
+        }
+
+        Ok(items)
+    }
+}
+
+impl<A: Codec, B: Codec> Codec for (A, B) {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.0.encode(writer)?;
+        self.1.encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Ok((A::decode(reader)?, B::decode(reader)?))
+    }
+}
+
+/// Reads a `u64` length and then that many bytes, growing the buffer only
+/// as the bytes come in.
+fn read_bytes<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = u64::decode(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(bytes)
+}
+
+/// Why [`TreapMap::read_snapshot`] rejected its input.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Reading failed, or a key or value could not be decoded.
+    Io(io::Error),
+    /// The input doesn't start like a snapshot.
+    BadMagic,
+    /// The snapshot was written in a format version this crate can't read.
+    UnsupportedVersion(u32),
+    /// The input ended before the snapshot did.
+    Truncated,
+    /// The checksum at the end doesn't match the bytes before it.
+    Checksum { stored: u64, computed: u64 },
+    /// The children the nodes claim to have don't add up to a tree with as
+    /// many nodes as the header says.
+    Shape,
+    /// The tree is well formed, but its keys or priorities are out of
+    /// order.
+    Invariant(InvariantError),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "failed to read snapshot: {err}"),
+            SnapshotError::BadMagic => f.write_str("not a treap snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {version}")
+            }
+            SnapshotError::Truncated => f.write_str("snapshot is truncated"),
+            SnapshotError::Checksum { stored, computed } => write!(
+                f,
+                "snapshot checksum is {computed:#018x} but {stored:#018x} was stored"
+            ),
+            SnapshotError::Shape => f.write_str("snapshot nodes don't form a tree of its length"),
+            SnapshotError::Invariant(err) => write!(f, "snapshot is out of order: {err}"),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SnapshotError::Io(err) => Some(err),
+            SnapshotError::Invariant(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => SnapshotError::Truncated,
+            _ => SnapshotError::Io(err),
+        }
+    }
+}
+
+impl<K, V, P, M: Monoid<V>> TreapMap<K, V, P, M> {
+    /// Writes the map to `writer` in a compact binary format that
+    /// [`TreapMap::read_snapshot`] turns back into the very same tree.
+    ///
+    /// The snapshot is a header (the bytes `TRPS`, a `u32` format version
+    /// and the `u64` number of entries), then every node in preorder (its
+    /// `u64` priority, a byte flagging which children it has, its key and
+    /// its value), then an FNV-1a hash of everything before it. Numbers are
+    /// little-endian, keys and values go through [`Codec`].
+    ///
+    /// Writes are small, so `writer` had better be buffered.
+    ///
+    /// ```
+    /// use treap::map::TreapMap;
+    ///
+    /// let mut map = TreapMap::new();
+    /// map.insert(1u32, String::from("a"));
+    /// map.insert(2, String::from("b"));
+    ///
+    /// let mut bytes = Vec::new();
+    /// map.write_snapshot(&mut bytes).unwrap();
+    ///
+    /// let copy: TreapMap<u32, String> = TreapMap::read_snapshot(&bytes[..]).unwrap();
+    /// assert!(copy.iter().eq(map.iter()));
+    /// ```
+    pub fn write_snapshot<W: Write>(&self, writer: W) -> io::Result<()>
+    where
+        K: Codec,
+        V: Codec,
+    {
+        let mut writer = Hashing::new(writer);
+
+        writer.write_all(&MAGIC)?;
+        VERSION.encode(&mut writer)?;
+        (self.len as u64).encode(&mut writer)?;
+
+        let mut stack: Vec<_> = self.root.as_deref().into_iter().collect();
+        while let Some(node) = stack.pop() {
+            let mut flags = 0;
+            if node.left().is_some() {
+                flags |= HAS_LEFT;
+            }
+            if node.right().is_some() {
+                flags |= HAS_RIGHT;
+            }
+
+            node.priority().encode(&mut writer)?;
+            flags.encode(&mut writer)?;
+            node.key().encode(&mut writer)?;
+            node.val().encode(&mut writer)?;
+
+            // pushed in reverse: the left subtree comes out first
+            stack.extend(node.right());
+            stack.extend(node.left());
+        }
+
+        let hash = writer.hash;
+        hash.encode(&mut writer.inner)?;
+        writer.inner.flush()
+    }
+
+    /// Reads a map written by [`TreapMap::write_snapshot`], rebuilding its
+    /// tree node by node in O(n), without a single rotation.
+    ///
+    /// Checks the header and checksum, and that the keys and priorities are
+    /// in order, so that a corrupted snapshot is rejected rather than
+    /// turned into a broken map. New priorities are drawn from
+    /// `P::default()`.
+    pub fn read_snapshot<R: Read>(reader: R) -> Result<Self, SnapshotError>
+    where
+        K: Codec + Ord,
+        V: Codec,
+        P: Default,
+    {
+        let mut reader = Hashing::new(reader);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+
+        let version = u32::decode(&mut reader)?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let len = u64::decode(&mut reader)?;
+        let mut tree = Preorder::new();
+        for _ in 0..len {
+            let priority = u64::decode(&mut reader)?;
+            let flags = u8::decode(&mut reader)?;
+            if flags & !(HAS_LEFT | HAS_RIGHT) != 0 {
+                return Err(SnapshotError::Shape);
+            }
+
+            let key = K::decode(&mut reader)?;
+            let val = V::decode(&mut reader)?;
+            let (left, right) = (flags & HAS_LEFT != 0, flags & HAS_RIGHT != 0);
+            if !tree.push(key, val, priority, left, right) {
+                return Err(SnapshotError::Shape);
+            }
+        }
+        let root = tree.finish().ok_or(SnapshotError::Shape)?;
+
+        let computed = reader.hash;
+        let stored = u64::decode(&mut reader.inner)?;
+        if stored != computed {
+            return Err(SnapshotError::Checksum { stored, computed });
+        }
+
+        let mut map = Self::empty(P::default());
+        map.root = root;
+        map.len = usize::try_from(len).map_err(|_| SnapshotError::Shape)?;
+        map.check(|a, b| a < b, |index, _| format!("#{index}"))
+            .map_err(SnapshotError::Invariant)?;
+
+        Ok(map)
+    }
+}
+
+/// Passes bytes through to or from `inner`, keeping a running FNV-1a hash
+/// of them.
+struct Hashing<T> {
+    inner: T,
+    hash: u64,
+}
+
+impl<T> Hashing<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash ^= u64::from(byte);
+            self.hash = self.hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+impl<W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> TreapMap<u32, String> {
+        let mut map = TreapMap::with_seed(25);
+        for i in 0..500 {
+            map.insert(i * 7 % 500, format!("value {i}"));
+        }
+
+        map
+    }
+
+    fn snapshot<K: Codec, V: Codec>(map: &TreapMap<K, V>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        map.write_snapshot(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn read(bytes: &[u8]) -> Result<TreapMap<u32, String>, SnapshotError> {
+        TreapMap::read_snapshot(bytes)
+    }
+
+    /// Replaces the checksum at the end of `bytes` with a fresh one.
+    fn rehash(bytes: &mut Vec<u8>) {
+        bytes.truncate(bytes.len() - 8);
+        let mut hashing = Hashing::new(());
+        hashing.update(bytes);
+        bytes.extend(hashing.hash.to_le_bytes());
+    }
+
+    #[test]
+    fn round_trip_keeps_shape() {
+        let map = sample();
+        let copy = read(&snapshot(&map)).unwrap();
+
+        let draw = |map: &TreapMap<u32, String>| {
+            map.pretty_tree(usize::MAX, u32::to_string, String::clone)
+        };
+        assert_eq!(draw(&copy), draw(&map));
+        assert_eq!(copy.len(), map.len());
+        assert_eq!(copy.validate(), Ok(()));
+
+        let empty = read(&snapshot(&TreapMap::<u32, String>::new())).unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let mut bytes = snapshot(&sample());
+
+        bytes[0] = b'X';
+        assert!(matches!(read(&bytes), Err(SnapshotError::BadMagic)));
+
+        bytes[0] = b'T';
+        bytes[4] = 9;
+        assert!(matches!(
+            read(&bytes),
+            Err(SnapshotError::UnsupportedVersion(9))
+        ));
+    }
+
+    #[test]
+    fn rejects_corruption() {
+        let bytes = snapshot(&sample());
+
+        for cut in [3, 20, bytes.len() - 1] {
+            assert!(matches!(read(&bytes[..cut]), Err(SnapshotError::Truncated)));
+        }
+
+        // the last byte of the last value
+        let mut flipped = bytes.clone();
+        let at = flipped.len() - 9;
+        flipped[at] ^= 1;
+        assert!(matches!(
+            read(&flipped),
+            Err(SnapshotError::Checksum { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_broken_trees() {
+        // a root claiming a left child, with only one node in the snapshot
+        let mut map = TreapMap::new();
+        map.insert(1u32, String::new());
+        let mut bytes = snapshot(&map);
+        bytes[24] = HAS_LEFT;
+        rehash(&mut bytes);
+        assert!(matches!(read(&bytes), Err(SnapshotError::Shape)));
+
+        // two nodes whose priorities are swapped
+        let map = TreapMap::from_sorted_iter([(1u32, ()), (2, ())]);
+        let mut bytes = snapshot(&map);
+        let (root, child) = (16..24, 29..37);
+        let priority = bytes[root.clone()].to_vec();
+        bytes.copy_within(child.clone(), root.start);
+        bytes[child].copy_from_slice(&priority);
+        rehash(&mut bytes);
+
+        let err = TreapMap::<u32, ()>::read_snapshot(&bytes[..]).unwrap_err();
+        assert!(matches!(
+            err,
+            SnapshotError::Invariant(InvariantError::Heap { .. })
+        ));
+    }
+}
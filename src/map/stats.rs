@@ -0,0 +1,108 @@
+use std::mem;
+
+use crate::{monoid::Monoid, node::Node};
+
+use super::TreapMap;
+
+/// The shape of a [`TreapMap`]'s tree, see [`TreapMap::stats`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Number of nodes, which is also the length of the map.
+    pub nodes: usize,
+    /// Number of levels in the tree, zero when it is empty.
+    pub height: usize,
+    /// Depth of the deepest node, the root being at depth zero.
+    pub max_depth: usize,
+    /// Mean depth over all nodes, about `2 ln n` for a balanced treap.
+    pub average_depth: f64,
+    /// Number of nodes at each depth, starting at the root.
+    pub depth_histogram: Vec<usize>,
+    /// Bytes taken by the node allocations, not counting anything keys and
+    /// values themselves point to.
+    pub heap_bytes: usize,
+}
+
+impl<K, V, P, M: Monoid<V>> TreapMap<K, V, P, M> {
+    /// Walks the whole tree to measure its shape, in O(n).
+    ///
+    /// ```
+    /// use treap::map::TreapMap;
+    ///
+    /// let mut map = TreapMap::new();
+    /// for i in 0..1000 {
+    ///     map.insert(i, ());
+    /// }
+    ///
+    /// let stats = map.stats();
+    /// assert_eq!(stats.nodes, 1000);
+    /// assert!(stats.average_depth < 30.0);
+    /// ```
+    pub fn stats(&self) -> Stats {
+        let mut histogram: Vec<usize> = Vec::new();
+        let mut stack: Vec<_> = self
+            .root
+            .as_deref()
+            .map(|root| (root, 0))
+            .into_iter()
+            .collect();
+
+        while let Some((node, depth)) = stack.pop() {
+            if histogram.len() <= depth {
+                histogram.push(0);
+            }
+            histogram[depth] += 1;
+
+            stack.extend(node.left().map(|left| (left, depth + 1)));
+            stack.extend(node.right().map(|right| (right, depth + 1)));
+        }
+
+        let nodes: usize = histogram.iter().sum();
+        let total_depth: usize = histogram.iter().enumerate().map(|(d, n)| d * n).sum();
+
+        Stats {
+            nodes,
+            height: histogram.len(),
+            max_depth: histogram.len().saturating_sub(1),
+            average_depth: if nodes == 0 {
+                0.0
+            } else {
+                total_depth as f64 / nodes as f64
+            },
+            depth_histogram: histogram,
+            heap_bytes: nodes * mem::size_of::<Node<K, V, M>>(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let map: TreapMap<u32, u32> = TreapMap::new();
+        assert_eq!(map.stats(), Stats::default());
+    }
+
+    #[test]
+    fn balanced_shape() {
+        let mut map = TreapMap::with_seed(23);
+        for i in 0..(1 << 14) {
+            map.insert(i, i);
+        }
+
+        let stats = map.stats();
+        assert_eq!(stats.nodes, 1 << 14);
+        assert_eq!(stats.depth_histogram.iter().sum::<usize>(), stats.nodes);
+        assert_eq!(stats.depth_histogram[0], 1);
+        assert_eq!(stats.height, stats.max_depth + 1);
+        assert_eq!(
+            stats.heap_bytes,
+            stats.nodes * mem::size_of::<Node<u32, u32>>()
+        );
+
+        // 2 ln n is about 19.4, and the height stays within a few times log n
+        assert!(stats.average_depth > 10.0 && stats.average_depth < 30.0);
+        assert!(stats.height < 4 * 14);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{error::Error, fmt};
+
+use crate::{monoid::Monoid, node::Node};
+
+use super::TreapMap;
+
+/// The first broken invariant found by [`TreapMap::validate`].
+///
+/// Keys are named by their `Debug` output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvariantError {
+    /// Two keys next to each other in order are not strictly ascending.
+    Order { before: String, after: String },
+    /// A child has a higher priority than its parent.
+    Heap { parent: String, child: String },
+    /// The subtree size cached in a node doesn't match its children.
+    Size {
+        key: String,
+        cached: usize,
+        actual: usize,
+    },
+    /// The length of the map doesn't match the number of nodes.
+    Len { cached: usize, actual: usize },
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantError::Order { before, after } => {
+                write!(f, "key {after} comes after key {before} but is not greater")
+            }
+            InvariantError::Heap { parent, child } => {
+                write!(
+                    f,
+                    "key {child} has a higher priority than its parent {parent}"
+                )
+            }
+            InvariantError::Size {
+                key,
+                cached,
+                actual,
+            } => write!(
+                f,
+                "subtree at key {key} has {actual} nodes but caches a size of {cached}"
+            ),
+            InvariantError::Len { cached, actual } => {
+                write!(f, "map has {actual} nodes but a length of {cached}")
+            }
+        }
+    }
+}
+
+impl Error for InvariantError {}
+
+impl<K, V, P, M: Monoid<V>> TreapMap<K, V, P, M> {
+    /// Checks that the keys are in order, that the priorities are in heap
+    /// order, and that the cached subtree sizes and length are right.
+    ///
+    /// Takes O(n). Enabling the `debug-invariants` feature runs the same
+    /// checks after every change to the shape of the tree, panicking on the
+    /// first failure.
+    pub fn validate(&self) -> Result<(), InvariantError>
+    where
+        K: Ord + fmt::Debug,
+    {
+        self.check(|a, b| a < b, |_, key| format!("{key:?}"))
+    }
+
+    pub(super) fn check<F, D>(&self, ordered: F, describe: D) -> Result<(), InvariantError>
+    where
+        F: FnMut(&K, &K) -> bool,
+        D: Fn(usize, &K) -> String,
+    {
+        let actual = Node::validate(&self.root, ordered, describe)?;
+        if actual != self.len {
+            return Err(InvariantError::Len {
+                cached: self.len,
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Panics if an invariant is broken, when the `debug-invariants` feature
+    /// is on.
+    pub(super) fn debug_validate(&self)
+    where
+        K: Ord,
+    {
+        self.debug_check(|a, b| a < b);
+    }
+
+    /// Like [`TreapMap::debug_validate`], for callers that can't compare
+    /// keys.
+    pub(super) fn debug_validate_unordered(&self) {
+        self.debug_check(|_, _| true);
+    }
+
+    #[inline(always)]
+    fn debug_check<F: FnMut(&K, &K) -> bool>(&self, ordered: F) {
+        // keys don't have to be `Debug` here, so they are named by position
+        #[cfg(feature = "debug-invariants")]
+        if let Err(err) = self.check(ordered, |index, _| format!("#{index}")) {
+            panic!("TreapMap invariant broken: {err}");
+        }
+
+        #[cfg(not(feature = "debug-invariants"))]
+        let _ = ordered;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_after_writes() {
+        let mut map = TreapMap::with_seed(21);
+        assert_eq!(map.validate(), Ok(()));
+
+        for i in 0..1000 {
+            map.insert(i * 7 % 1000, i);
+        }
+        for i in (0..1000).step_by(3) {
+            map.remove(&i);
+        }
+        let mut tail = map.split_off(&500);
+        assert_eq!(map.validate(), Ok(()));
+        assert_eq!(tail.validate(), Ok(()));
+
+        tail.append(&mut map);
+        assert_eq!(tail.validate(), Ok(()));
+    }
+
+    #[test]
+    fn len_mismatch() {
+        let mut map = TreapMap::new();
+        map.insert("a", 1);
+        map.insert("b", 2);
+        map.len = 3;
+
+        let err = map.validate().unwrap_err();
+        assert_eq!(
+            err,
+            InvariantError::Len {
+                cached: 3,
+                actual: 2
+            }
+        );
+        assert_eq!(err.to_string(), "map has 2 nodes but a length of 3");
+    }
+}
@@ -0,0 +1,94 @@
+/// An associative way of summarizing values, with an identity.
+///
+/// A [`TreapMap`](crate::map::TreapMap) folding its values with a monoid
+/// caches the fold of every subtree in its root, and keeps it up to date
+/// through insertions, removals and rotations, so that
+/// [`TreapMap::fold_range`](crate::map::TreapMap::fold_range) can answer in
+/// O(log n) for any range of keys.
+///
+/// The monoid is a marker type, the fold itself is its `Agg`:
+///
+/// ```
+/// use treap::{map::TreapMap, monoid::Monoid};
+///
+/// struct Max;
+///
+/// impl Monoid<u32> for Max {
+///     type Agg = u32;
+///
+///     fn identity() -> u32 {
+///         0
+///     }
+///
+///     fn lift(val: &u32) -> u32 {
+///         *val
+///     }
+///
+///     fn combine(left: &u32, right: &u32) -> u32 {
+///         *left.max(right)
+///     }
+/// }
+///
+/// let mut map: TreapMap<_, _, _, Max> = TreapMap::with_monoid();
+/// map.insert("a", 3);
+/// map.insert("b", 7);
+/// map.insert("c", 5);
+///
+/// assert_eq!(map.fold_range("a".."b"), 3);
+/// assert_eq!(map.fold_range::<&str, _>(..), 7);
+/// ```
+pub trait Monoid<V> {
+    type Agg;
+
+    /// The fold of no values at all.
+    fn identity() -> Self::Agg;
+
+    /// The fold of a single value.
+    fn lift(val: &V) -> Self::Agg;
+
+    /// Folds two adjacent runs of values, `left` coming first in key order.
+    ///
+    /// Has to be associative, but not necessarily commutative.
+    fn combine(left: &Self::Agg, right: &Self::Agg) -> Self::Agg;
+}
+
+/// The monoid of maps that don't fold their values, which costs nothing to
+/// maintain.
+impl<V> Monoid<V> for () {
+    type Agg = ();
+
+    fn identity() {}
+
+    fn lift(_: &V) {}
+
+    fn combine(_: &(), _: &()) {}
+}
+
+/// An update applied to every value in a range of keys at once, such as
+/// adding a constant or overwriting with one.
+///
+/// A [`LazyTreapMap`](crate::lazy::LazyTreapMap) applies an action to the
+/// root of the subtree holding the range and leaves it there as a pending
+/// tag, which is only handed down to the children when something looks
+/// below that node. Pending tags compose, so stacking updates on the same
+/// range stays O(log n) each.
+pub trait Action<V, M: Monoid<V>>: Clone {
+    /// Applies the action to a single value.
+    fn apply(&self, val: &mut V);
+
+    /// Updates the fold of `len` values to what it would have been had the
+    /// action been applied to each of them first.
+    fn apply_fold(&self, agg: &mut M::Agg, len: usize);
+
+    /// Returns the action that applies `self`, then `then`.
+    fn compose(&self, then: &Self) -> Self;
+}
+
+/// The action of maps without range updates, which does nothing.
+impl<V, M: Monoid<V>> Action<V, M> for () {
+    fn apply(&self, _: &mut V) {}
+
+    fn apply_fold(&self, _: &mut M::Agg, _: usize) {}
+
+    fn compose(&self, _: &()) {}
+}
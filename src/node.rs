@@ -1,131 +1,1699 @@
-use std::{borrow::Borrow, cmp::Ordering};
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    collections::VecDeque,
+    mem::ManuallyDrop,
+    ops::{Bound, RangeBounds},
+    ptr::{self, NonNull},
+};
 
-pub(crate) struct Node<K, V> {
+use crate::{
+    counters,
+    map::InvariantError,
+    monoid::{Action, Monoid},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    pub fn opposite(self) -> Self {
+        match self {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        }
+    }
+}
+
+/// Nodes from the root down to some point in the tree, each paired with the
+/// side the path continues on.
+pub(crate) type Path<K, V, M = (), A = ()> = Vec<(NonNull<Node<K, V, M, A>>, Side)>;
+
+pub(crate) type Tree<K, V, M = (), A = ()> = Option<Box<Node<K, V, M, A>>>;
+
+/// The parts a tree is split into around a key: less, equal and greater.
+pub(crate) type Pieces<K, V, M, A> = (Tree<K, V, M, A>, Tree<K, V, M, A>, Tree<K, V, M, A>);
+
+/// The parts a tree is split into at a position: before and after it.
+pub(crate) type Halves<K, V, M, A> = (Tree<K, V, M, A>, Tree<K, V, M, A>);
+
+pub(crate) struct Node<K, V, M: Monoid<V> = (), A: Action<V, M> = ()> {
     key: K,
     val: V,
-    left: Option<Box<Node<K, V>>>,
-    right: Option<Box<Node<K, V>>>,
+    left: Tree<K, V, M, A>,
+    right: Tree<K, V, M, A>,
     priority: u64,
+    /// Number of nodes in the subtree rooted here, this one included.
+    size: usize,
+    /// Whether the subtree below this node is still to be mirrored. Set on
+    /// position-keyed trees only, and applied lazily by [`Node::push_down`].
+    reversed: bool,
+    /// Fold of the values in the subtree rooted here, in key order.
+    agg: M::Agg,
+    /// Action already applied to this node's value and fold, but still to
+    /// be handed down to its children by [`Node::push_down`].
+    tag: Option<A>,
+    /// Tells this entry apart from earlier ones with the same key, for the
+    /// handles given out to it. Zero until the first one is.
+    stamp: u32,
 }
 
-impl<K, V> Node<K, V> {
-    pub fn new(key: K, val: V, priority: u64) -> Self {
+impl<K, V, M: Monoid<V>, A: Action<V, M>> Node<K, V, M, A> {
+    fn new(key: K, val: V, priority: u64) -> Self {
         Self {
+            agg: M::lift(&val),
+            tag: None,
             key,
             val,
             left: None,
             right: None,
             priority,
+            size: 1,
+            reversed: false,
+            stamp: 0,
+        }
+    }
+
+    /// Allocates a node for a new entry.
+    pub fn boxed(key: K, val: V, priority: u64) -> Box<Self> {
+        counters::count_allocation();
+
+        Box::new(Self::new(key, val, priority))
+    }
+
+    pub fn size(tree: &Tree<K, V, M, A>) -> usize {
+        tree.as_ref().map_or(0, |node| node.size)
+    }
+
+    pub fn insert(tree: &mut Tree<K, V, M, A>, key: K, val: V, priority: u64) -> Option<V>
+    where
+        K: Ord,
+    {
+        let (path, found) = Self::search(tree, &key);
+
+        match found {
+            Some(mut node) => {
+                // SAFETY: `node` and `path` were just found in `tree`
+                unsafe {
+                    let node_ref = node.as_mut();
+
+                    // we don't update the key
+                    // See rationale in std::collections::BtreeMap docs.
+                    let old_val = std::mem::replace(&mut node_ref.val, val);
+                    node_ref.update();
+
+                    let above = if node_ref.priority < priority {
+                        node_ref.priority = priority;
+                        Self::sift_up(&path, node).0
+                    } else {
+                        path.len()
+                    };
+                    Self::update_path(&path[..above]);
+
+                    Some(old_val)
+                }
+            }
+            None => {
+                // SAFETY: `path` was just recorded on `tree` and ends in the
+                // empty slot where the key belongs
+                unsafe { Self::attach(tree, &path, Self::boxed(key, val, priority)) };
+
+                None
+            }
+        }
+    }
+
+    pub fn remove<Q>(tree: &mut Tree<K, V, M, A>, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let (path, found) = Self::search(tree, key);
+        found?;
+
+        // SAFETY: `path` was just recorded on `tree` and ends above `found`
+        unsafe { Self::detach(tree, &path) }
+    }
+
+    /// Removes the node at position `index` in key order.
+    pub fn remove_index(tree: &mut Tree<K, V, M, A>, index: usize) -> Option<(K, V)> {
+        let (path, found) = Self::search_index(tree, index);
+        found?;
+
+        // SAFETY: `path` was just recorded on `tree` and ends above `found`
+        unsafe { Self::detach(tree, &path) }
+    }
+
+    /// Removes the node at the top of `tree`.
+    ///
+    /// Its children are merged into its place, which gives the same tree as
+    /// rotating it down until it is a leaf.
+    pub fn unlink(tree: &mut Tree<K, V, M, A>) -> Option<(K, V)> {
+        let mut node = tree.take()?;
+        node.push_down();
+        *tree = Self::merge(node.left.take(), node.right.take());
+
+        Some((*node).into_entry())
+    }
+
+    /// Returns the node holding `key`, handing pending actions down on the
+    /// way.
+    pub fn find_mut<'a, Q>(tree: &'a mut Tree<K, V, M, A>, key: &Q) -> Option<&'a mut Self>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let mut node = tree.as_deref_mut()?;
+
+        loop {
+            node.push_down();
+
+            match key.cmp(node.key.borrow()) {
+                Ordering::Equal => return Some(node),
+                Ordering::Less => node = node.left.as_deref_mut()?,
+                Ordering::Greater => node = node.right.as_deref_mut()?,
+            }
+        }
+    }
+
+    /// Looks for `key` and records every node passed on the way down.
+    ///
+    /// Returns the found node, if any, along with the nodes above it, or
+    /// above the empty slot where the key would go.
+    pub fn search<Q>(
+        tree: &mut Tree<K, V, M, A>,
+        key: &Q,
+    ) -> (Path<K, V, M, A>, Option<NonNull<Self>>)
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        Self::search_by(tree, |node| {
+            counters::count_comparison();
+            key.cmp(node.key.borrow())
+        })
+    }
+
+    /// Like [`Node::search`], looking for the node at position `index` in
+    /// key order.
+    pub fn search_index(
+        tree: &mut Tree<K, V, M, A>,
+        mut index: usize,
+    ) -> (Path<K, V, M, A>, Option<NonNull<Self>>) {
+        Self::search_by(tree, |node| {
+            let left = Self::size(&node.left);
+            let ord = index.cmp(&left);
+
+            if ord == Ordering::Greater {
+                index -= left + 1;
+            }
+
+            ord
+        })
+    }
+
+    /// Records the nodes above the empty slot where a new node would have to
+    /// go to end up at position `index`.
+    pub fn search_gap(tree: &mut Tree<K, V, M, A>, mut index: usize) -> Path<K, V, M, A> {
+        let (path, _) = Self::search_by(tree, |node| {
+            let left = Self::size(&node.left);
+
+            if index <= left {
+                Ordering::Less
+            } else {
+                index -= left + 1;
+                Ordering::Greater
+            }
+        });
+
+        path
+    }
+
+    fn search_by<F>(
+        tree: &mut Tree<K, V, M, A>,
+        mut cmp: F,
+    ) -> (Path<K, V, M, A>, Option<NonNull<Self>>)
+    where
+        F: FnMut(&Self) -> Ordering,
+    {
+        let mut path = Vec::new();
+        let mut cur = tree.as_deref_mut().map(NonNull::from);
+
+        while let Some(mut ptr) = cur {
+            // SAFETY: `ptr` came from a live `&mut` into the tree and nothing
+            // else borrows the tree while we search it
+            let node = unsafe { ptr.as_mut() };
+            node.push_down();
+
+            let side = match cmp(node) {
+                Ordering::Equal => return (path, Some(ptr)),
+                Ordering::Less => Side::Left,
+                Ordering::Greater => Side::Right,
+            };
+
+            path.push((ptr, side));
+            cur = node.child_mut(side).as_deref_mut().map(NonNull::from);
+        }
+
+        (path, None)
+    }
+
+    /// Returns the slot at the end of `path`.
+    ///
+    /// # Safety
+    ///
+    /// `path` must have been produced by [`Node::search`] on `tree`, and the
+    /// tree must not have been restructured since.
+    unsafe fn slot<'a>(
+        tree: &'a mut Tree<K, V, M, A>,
+        path: &[(NonNull<Self>, Side)],
+    ) -> &'a mut Tree<K, V, M, A> {
+        match path.last() {
+            Some(&(mut parent, side)) => parent.as_mut().child_mut(side),
+            None => tree,
+        }
+    }
+
+    /// Removes the node in the slot at the end of `path`.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Node::slot`].
+    pub unsafe fn detach(
+        tree: &mut Tree<K, V, M, A>,
+        path: &[(NonNull<Self>, Side)],
+    ) -> Option<(K, V)> {
+        let removed = Self::unlink(Self::slot(tree, path))?;
+        Self::update_path(path);
+
+        Some(removed)
+    }
+
+    /// Hangs `leaf` in the empty slot at the end of `path` and rotates it up
+    /// until the heap invariant holds again.
+    ///
+    /// Returns the node that ends up holding the new entry. Only the nodes of
+    /// `path` above that one are still valid afterwards.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Node::slot`], and the slot at the end of `path` must be
+    /// empty.
+    pub unsafe fn attach(
+        tree: &mut Tree<K, V, M, A>,
+        path: &[(NonNull<Self>, Side)],
+        leaf: Box<Self>,
+    ) -> NonNull<Self> {
+        let slot = Self::slot(tree, path);
+        debug_assert!(slot.is_none());
+
+        let at = NonNull::from(&mut **slot.insert(leaf));
+        let (above, at) = Self::sift_up(path, at);
+        Self::update_path(&path[..above]);
+
+        at
+    }
+
+    /// Recomputes the cached fields of every node on `path`, bottom up.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Node::slot`].
+    unsafe fn update_path(path: &[(NonNull<Self>, Side)]) {
+        for &(mut ancestor, _) in path.iter().rev() {
+            ancestor.as_mut().update();
+        }
+    }
+
+    /// Rotates the node `at`, found at the end of `path`, up past every
+    /// ancestor with a lower priority.
+    ///
+    /// Returns how many nodes of `path` are left above the entry that was
+    /// in `at`, and the node that ends up holding it. Each rotation updates
+    /// the two nodes it turns, the ones above are left to the caller.
+    ///
+    /// # Safety
+    ///
+    /// `path` must lead from the root down to `at`, and must not have been
+    /// invalidated by restructuring the tree. A rotation moves the boxes
+    /// below the ancestor it turns, so only the nodes of `path` above the
+    /// returned count are valid afterwards.
+    unsafe fn sift_up(
+        path: &[(NonNull<Self>, Side)],
+        mut at: NonNull<Self>,
+    ) -> (usize, NonNull<Self>) {
+        let mut above = path.len();
+
+        for &(mut ancestor, side) in path.iter().rev() {
+            let ancestor = ancestor.as_mut();
+
+            if !ancestor.is_heap_property_violated(ancestor.child(side)) {
+                break;
+            }
+
+            // rotations swap node contents, so the entry moves up into the
+            // ancestor's allocation
+            match side {
+                Side::Left => ancestor.rotate_right(),
+                Side::Right => ancestor.rotate_left(),
+            }
+            at = NonNull::from(ancestor);
+            above -= 1;
+        }
+
+        (above, at)
+    }
+
+    /// Splits `tree` into the nodes with keys less than `key`, the node
+    /// holding `key` if there is one, and the nodes with greater keys.
+    pub fn split<Q>(mut tree: Tree<K, V, M, A>, key: &Q) -> Pieces<K, V, M, A>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        // the nodes passed on the way down end up on the right spine of the
+        // lesser part or on the left spine of the greater one
+        let mut less = Vec::new();
+        let mut greater = Vec::new();
+
+        while let Some(mut node) = tree {
+            node.push_down();
+
+            match key.cmp(node.key.borrow()) {
+                Ordering::Less => {
+                    tree = node.left.take();
+                    greater.push((node, Side::Left));
+                }
+                Ordering::Greater => {
+                    tree = node.right.take();
+                    less.push((node, Side::Right));
+                }
+                Ordering::Equal => {
+                    let less = Self::hang(less, node.left.take());
+                    let greater = Self::hang(greater, node.right.take());
+                    node.update();
+
+                    return (less, Some(node), greater);
+                }
+            }
+        }
+
+        (Self::hang(less, None), None, Self::hang(greater, None))
+    }
+
+    /// Splits `tree` into its first `index` nodes in key order and the rest.
+    pub fn split_at(mut tree: Tree<K, V, M, A>, mut index: usize) -> Halves<K, V, M, A> {
+        let mut less = Vec::new();
+        let mut greater = Vec::new();
+
+        while let Some(mut node) = tree {
+            node.push_down();
+
+            let left = Self::size(&node.left);
+            if index <= left {
+                tree = node.left.take();
+                greater.push((node, Side::Left));
+            } else {
+                index -= left + 1;
+                tree = node.right.take();
+                less.push((node, Side::Right));
+            }
+        }
+
+        (Self::hang(less, None), Self::hang(greater, None))
+    }
+
+    /// Joins two trees where every key in `left` is less than every key in
+    /// `right`, descending only along their inner spines.
+    pub fn merge(mut left: Tree<K, V, M, A>, mut right: Tree<K, V, M, A>) -> Tree<K, V, M, A> {
+        // the higher of the two roots goes on top, and the merge carries on
+        // in its inner child
+        let mut spine = Vec::new();
+
+        loop {
+            match (left, right) {
+                (None, tree) | (tree, None) => return Self::hang(spine, tree),
+                (Some(mut l), Some(mut r)) => {
+                    l.push_down();
+                    r.push_down();
+
+                    if l.priority > r.priority {
+                        (left, right) = (l.right.take(), Some(r));
+                        spine.push((l, Side::Right));
+                    } else {
+                        (left, right) = (Some(l), r.left.take());
+                        spine.push((r, Side::Left));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hangs `bottom` below the last node of `spine`, each node below the
+    /// one before it on the given side, and returns the topmost node.
+    fn hang(spine: Vec<(Box<Self>, Side)>, bottom: Tree<K, V, M, A>) -> Tree<K, V, M, A> {
+        spine
+            .into_iter()
+            .rev()
+            .fold(bottom, |below, (mut node, side)| {
+                *node.child_mut(side) = below;
+                node.update();
+                Some(node)
+            })
+    }
+
+    /// Merges two trees with overlapping keys. `resolve` is called with the
+    /// key and both values, in argument order, whenever a key is in both.
+    pub fn union<F>(a: Tree<K, V, M, A>, b: Tree<K, V, M, A>, resolve: &mut F) -> Tree<K, V, M, A>
+    where
+        K: Ord,
+        F: FnMut(&K, V, V) -> V,
+    {
+        divide(
+            (a, b, false),
+            |(a, b, swapped)| {
+                // keep the higher priority root on top, but remember to hand
+                // the values to `resolve` in the original order
+                let (mut a, b, swapped) = match (a, b) {
+                    (None, tree) | (tree, None) => return Divide::Done(tree),
+                    (Some(a), Some(b)) if a.priority < b.priority => (b, a, !swapped),
+                    (Some(a), Some(b)) => (a, b, swapped),
+                };
+                a.push_down();
+
+                let (less, equal, greater) = Self::split(Some(b), &a.key);
+                let (left, right) = (a.left.take(), a.right.take());
+
+                Divide::Split(
+                    (a, equal, swapped),
+                    (left, less, swapped),
+                    (right, greater, swapped),
+                )
+            },
+            |(mut a, equal, swapped), left, right| {
+                if let Some(equal) = equal {
+                    let (_, other) = (*equal).into_entry();
+
+                    // the value is moved out of `a` for `resolve` and the
+                    // result moved back in, and should `resolve` panic in
+                    // between, `a` is leaked rather than dropped without it
+                    let mut node = ManuallyDrop::new(a);
+                    // SAFETY: the value is read once and written back before
+                    // anything else looks at the node
+                    unsafe {
+                        let val = ptr::read(&node.val);
+                        let val = if swapped {
+                            resolve(&node.key, other, val)
+                        } else {
+                            resolve(&node.key, val, other)
+                        };
+                        ptr::write(&mut node.val, val);
+                    }
+                    a = ManuallyDrop::into_inner(node);
+                }
+
+                a.left = left;
+                a.right = right;
+                a.update();
+
+                Some(a)
+            },
+        )
+    }
+
+    /// Keeps the keys present in both trees, with the values from `a`.
+    pub fn intersection(a: Tree<K, V, M, A>, b: Tree<K, V, M, A>) -> Tree<K, V, M, A>
+    where
+        K: Ord,
+    {
+        divide(
+            (a, b, false),
+            |(a, b, swapped)| {
+                let (mut a, b, swapped) = match (a, b) {
+                    (Some(a), Some(b)) if a.priority < b.priority => (b, a, !swapped),
+                    (Some(a), Some(b)) => (a, b, swapped),
+                    _ => return Divide::Done(None),
+                };
+                a.push_down();
+
+                let (less, equal, greater) = Self::split(Some(b), &a.key);
+                let (left, right) = (a.left.take(), a.right.take());
+
+                Divide::Split(
+                    (a, equal, swapped),
+                    (left, less, swapped),
+                    (right, greater, swapped),
+                )
+            },
+            |(mut a, equal, swapped), left, right| match equal {
+                Some(equal) => {
+                    if swapped {
+                        a.val = (*equal).into_entry().1;
+                    }
+                    a.left = left;
+                    a.right = right;
+                    a.update();
+
+                    Some(a)
+                }
+                None => Self::merge(left, right),
+            },
+        )
+    }
+
+    /// Keeps the keys of `a` that are not in `b`.
+    pub fn difference(a: Tree<K, V, M, A>, b: Tree<K, V, M, A>) -> Tree<K, V, M, A>
+    where
+        K: Ord,
+    {
+        divide(
+            (a, b),
+            |(a, b)| {
+                let (a, mut b) = match (a, b) {
+                    (None, _) => return Divide::Done(None),
+                    (a, None) => return Divide::Done(a),
+                    (Some(a), Some(b)) => (a, b),
+                };
+                b.push_down();
+
+                let (less, _, greater) = Self::split(Some(a), &b.key);
+                Divide::Split((), (less, b.left.take()), (greater, b.right.take()))
+            },
+            |(), left, right| Self::merge(left, right),
+        )
+    }
+
+    /// Keeps the keys that are in exactly one of the two trees.
+    pub fn symmetric_difference(a: Tree<K, V, M, A>, b: Tree<K, V, M, A>) -> Tree<K, V, M, A>
+    where
+        K: Ord,
+    {
+        divide(
+            (a, b),
+            |(a, b)| {
+                let (mut a, b) = match (a, b) {
+                    (None, tree) | (tree, None) => return Divide::Done(tree),
+                    (Some(a), Some(b)) if a.priority < b.priority => (b, a),
+                    (Some(a), Some(b)) => (a, b),
+                };
+                a.push_down();
+
+                let (less, equal, greater) = Self::split(Some(b), &a.key);
+                let (left, right) = (a.left.take(), a.right.take());
+
+                Divide::Split((a, equal.is_some()), (left, less), (right, greater))
+            },
+            |(mut a, in_both), left, right| {
+                if in_both {
+                    Self::merge(left, right)
+                } else {
+                    a.left = left;
+                    a.right = right;
+                    a.update();
+
+                    Some(a)
+                }
+            },
+        )
+    }
+
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn priority(&self) -> u64 {
+        self.priority
+    }
+
+    /// Returns the number of nodes in the subtree rooted here.
+    pub fn subtree_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn stamp(&self) -> u32 {
+        self.stamp
+    }
+
+    pub fn set_stamp(&mut self, stamp: u32) {
+        self.stamp = stamp;
+    }
+
+    pub fn left(&self) -> Option<&Self> {
+        self.left.as_deref()
+    }
+
+    pub fn right(&self) -> Option<&Self> {
+        self.right.as_deref()
+    }
+
+    pub fn val(&self) -> &V {
+        &self.val
+    }
+
+    /// Moves the entry out of a node whose children have been detached.
+    fn into_entry(self) -> (K, V) {
+        debug_assert!(self.left.is_none() && self.right.is_none());
+
+        let mut node = ManuallyDrop::new(self);
+
+        // SAFETY: `node` is never touched again and its `Drop` never runs, so
+        // every field is either moved out or dropped here, exactly once
+        unsafe {
+            ptr::drop_in_place(&mut node.left);
+            ptr::drop_in_place(&mut node.right);
+            ptr::drop_in_place(&mut node.agg);
+            ptr::drop_in_place(&mut node.tag);
+
+            (ptr::read(&node.key), ptr::read(&node.val))
+        }
+    }
+
+    pub fn child(&self, side: Side) -> &Tree<K, V, M, A> {
+        match side {
+            Side::Left => &self.left,
+            Side::Right => &self.right,
+        }
+    }
+
+    pub fn child_mut(&mut self, side: Side) -> &mut Option<Box<Self>> {
+        match side {
+            Side::Left => &mut self.left,
+            Side::Right => &mut self.right,
+        }
+    }
+
+    /// Mirrors the subtree below this node.
+    ///
+    /// Only the flag is flipped here, the children are swapped the next time
+    /// something descends through this node.
+    pub fn reverse(&mut self) {
+        self.reversed = !self.reversed;
+    }
+
+    /// Applies a pending reversal to this node's children, handing it on to
+    /// their subtrees.
+    ///
+    /// Anything that looks at the children of a node on a mutable path has
+    /// to call this first, sizes and priorities are the only fields that
+    /// don't depend on it.
+    fn push_down(&mut self) {
+        if self.reversed {
+            self.reversed = false;
+            std::mem::swap(&mut self.left, &mut self.right);
+            for child in [&mut self.left, &mut self.right].into_iter().flatten() {
+                child.reverse();
+            }
+        }
+
+        if let Some(tag) = self.tag.take() {
+            for child in [&mut self.left, &mut self.right].into_iter().flatten() {
+                child.apply(&tag);
+            }
+        }
+    }
+
+    /// Applies `action` to every value in the subtree.
+    ///
+    /// Only this node's value and fold are updated right away, the action is
+    /// composed into the tag that [`Node::push_down`] hands on to the
+    /// children.
+    pub fn apply(&mut self, action: &A) {
+        action.apply(&mut self.val);
+        action.apply_fold(&mut self.agg, self.size);
+
+        self.tag = Some(match self.tag.take() {
+            Some(tag) => tag.compose(action),
+            None => action.clone(),
+        });
+    }
+
+    /// Folds the values whose keys fall within `range`.
+    ///
+    /// Like [`Walk::range`], only the two boundary paths are looked at,
+    /// subtrees inside the range contribute their cached fold as a whole.
+    /// Nothing is pushed down on the way: the tags pending above a node are
+    /// composed as we pass them and applied to the folds taken below.
+    pub fn fold<Q, R>(tree: &Tree<K, V, M, A>, range: &R) -> M::Agg
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let start = range.start_bound();
+        let end = range.end_bound();
+
+        // find the topmost node inside the range, both boundary paths fork
+        // off from there
+        let mut pending = None;
+        let mut tree = tree.as_deref();
+        let fork = loop {
+            let Some(node) = tree else {
+                return M::identity();
+            };
+
+            if !after_start(node.key.borrow(), start) {
+                tree = node.right.as_deref();
+            } else if !before_end(node.key.borrow(), end) {
+                tree = node.left.as_deref();
+            } else {
+                break node;
+            }
+            pending = node.pending_below(pending.as_ref());
+        };
+
+        let mut agg = fork.own_fold(pending.as_ref());
+        let below_fork = fork.pending_below(pending.as_ref());
+
+        let mut pending = below_fork.clone();
+        let mut tree = fork.left.as_deref();
+        while let Some(node) = tree {
+            let below = node.pending_below(pending.as_ref());
+
+            if after_start(node.key.borrow(), start) {
+                if let Some(right) = &node.right {
+                    agg = M::combine(&right.subtree_fold(below.as_ref()), &agg);
+                }
+                agg = M::combine(&node.own_fold(pending.as_ref()), &agg);
+                tree = node.left.as_deref();
+            } else {
+                tree = node.right.as_deref();
+            }
+            pending = below;
+        }
+
+        let mut pending = below_fork;
+        let mut tree = fork.right.as_deref();
+        while let Some(node) = tree {
+            let below = node.pending_below(pending.as_ref());
+
+            if before_end(node.key.borrow(), end) {
+                if let Some(left) = &node.left {
+                    agg = M::combine(&agg, &left.subtree_fold(below.as_ref()));
+                }
+                agg = M::combine(&agg, &node.own_fold(pending.as_ref()));
+                tree = node.right.as_deref();
+            } else {
+                tree = node.left.as_deref();
+            }
+            pending = below;
+        }
+
+        agg
+    }
+
+    /// Returns the action still to be applied to this node's children,
+    /// given the one `pending` from above on this node: its own tag first,
+    /// then `pending`.
+    fn pending_below(&self, pending: Option<&A>) -> Option<A> {
+        match (&self.tag, pending) {
+            (Some(tag), Some(pending)) => Some(tag.compose(pending)),
+            (Some(tag), None) => Some(tag.clone()),
+            (None, pending) => pending.cloned(),
+        }
+    }
+
+    /// Returns the fold of this node's value alone, with `pending` applied.
+    fn own_fold(&self, pending: Option<&A>) -> M::Agg {
+        let mut agg = M::lift(&self.val);
+        if let Some(pending) = pending {
+            pending.apply_fold(&mut agg, 1);
+        }
+
+        agg
+    }
+
+    /// Returns the fold of this node's subtree, with `pending` applied.
+    fn subtree_fold(&self, pending: Option<&A>) -> M::Agg {
+        // combining with the identity gives an owned copy without asking
+        // for `M::Agg: Clone`
+        let mut agg = M::combine(&M::identity(), &self.agg);
+        if let Some(pending) = pending {
+            pending.apply_fold(&mut agg, self.size);
+        }
+
+        agg
+    }
+
+    /// Recomputes the cached subtree size and fold from the children.
+    fn update(&mut self) {
+        self.size = 1 + Self::size(&self.left) + Self::size(&self.right);
+
+        let mut agg = M::lift(&self.val);
+        if let Some(left) = &self.left {
+            agg = M::combine(&left.agg, &agg);
+        }
+        if let Some(right) = &self.right {
+            agg = M::combine(&agg, &right.agg);
+        }
+        self.agg = agg;
+    }
+
+    fn is_heap_property_violated(&self, subtree: &Tree<K, V, M, A>) -> bool {
+        if let Some(child) = subtree.as_ref() {
+            self.priority < child.priority
+        } else {
+            false
+        }
+    }
+
+    //        y             x
+    //       / \           / \
+    //      x   c -->    a    y
+    //    / \                / \
+    //  a    b              b   c
+    fn rotate_right(&mut self) {
+        use std::mem;
+
+        self.push_down();
+        let x = self.left.take();
+
+        if let Some(mut x) = x {
+            counters::count_rotation();
+            x.push_down();
+            mem::swap(self, &mut x);
+            mem::swap(&mut self.right, &mut x.left);
+            x.update();
+            self.right = Some(x);
+            self.update();
+        }
+    }
+
+    //        y             x
+    //       / \           / \
+    //      a   x -->    y    c
+    //         / \      / \
+    //        b   c    a   b
+    fn rotate_left(&mut self) {
+        use std::mem;
+
+        self.push_down();
+        let x = self.right.take();
+
+        if let Some(mut x) = x {
+            counters::count_rotation();
+            x.push_down();
+            mem::swap(self, &mut x);
+            mem::swap(&mut self.left, &mut x.right);
+            x.update();
+            self.left = Some(x);
+            self.update();
+        }
+    }
+}
+
+// Reading through shared references can't apply pending actions, so only
+// trees without them get to do it.
+impl<K, V, M: Monoid<V>> Node<K, V, M> {
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.find(key).map(|node| &node.val)
+    }
+
+    /// Returns the node holding `key`.
+    pub fn find<Q>(&self, key: &Q) -> Option<&Self>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let mut node = self;
+
+        loop {
+            match key.cmp(node.key.borrow()) {
+                Ordering::Equal => return Some(node),
+                Ordering::Less => node = node.left.as_deref()?,
+                Ordering::Greater => node = node.right.as_deref()?,
+            }
+        }
+    }
+
+    pub fn first(&self) -> &Self {
+        let mut node = self;
+        let mut flipped = node.reversed;
+        while let (Some(left), _) = node.children(flipped) {
+            node = left;
+            flipped ^= node.reversed;
+        }
+
+        node
+    }
+
+    pub fn last(&self) -> &Self {
+        let mut node = self;
+        let mut flipped = node.reversed;
+        while let (_, Some(right)) = node.children(flipped) {
+            node = right;
+            flipped ^= node.reversed;
+        }
+
+        node
+    }
+
+    /// Returns the children in order, swapped if an ancestor's pending
+    /// reversal, or this node's own, mirrors them.
+    fn children(&self, flipped: bool) -> (Option<&Self>, Option<&Self>) {
+        if flipped {
+            (self.right.as_deref(), self.left.as_deref())
+        } else {
+            (self.left.as_deref(), self.right.as_deref())
+        }
+    }
+
+    /// Finds the node at position `index` in key order.
+    pub fn select(&self, mut index: usize) -> Option<&Self> {
+        let mut node = self;
+        let mut flipped = false;
+
+        loop {
+            flipped ^= node.reversed;
+            let (left, right) = node.children(flipped);
+            let left_size = left.map_or(0, |left| left.size);
+
+            match index.cmp(&left_size) {
+                Ordering::Equal => return Some(node),
+                Ordering::Less => node = left?,
+                Ordering::Greater => {
+                    index -= left_size + 1;
+                    node = right?;
+                }
+            }
         }
     }
 
-    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    /// Finds the position of `key` in key order, or where it would be
+    /// inserted if it is missing.
+    pub fn rank<Q>(&self, key: &Q) -> Result<usize, usize>
     where
         K: Borrow<Q> + Ord,
         Q: Ord + ?Sized,
     {
-        use std::cmp::Ordering;
+        let mut node = Some(self);
+        let mut before = 0;
 
-        match key.cmp(self.key.borrow()) {
-            Ordering::Equal => Some(&self.val),
-            Ordering::Less => self.left.as_ref().and_then(|n| n.get(key)),
-            Ordering::Greater => self.right.as_ref().and_then(|n| n.get(key)),
+        while let Some(n) = node {
+            match key.cmp(n.key.borrow()) {
+                Ordering::Equal => return Ok(before + Self::size(&n.left)),
+                Ordering::Less => node = n.left.as_deref(),
+                Ordering::Greater => {
+                    before += Self::size(&n.left) + 1;
+                    node = n.right.as_deref();
+                }
+            }
         }
+
+        Err(before)
     }
 
-    pub fn insert(&mut self, key: K, val: V, priority: u64) -> Option<V>
+    /// Finds the node with the smallest key that comes after `start`.
+    pub fn first_after<Q>(&self, start: Bound<&Q>) -> Option<&Self>
     where
-        K: Ord,
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
     {
-        match key.cmp(&self.key) {
-            Ordering::Equal => {
-                if self.priority < priority {
-                    self.priority = priority;
-                }
+        let mut node = Some(self);
+        let mut found = None;
 
-                // we don't update the key
-                // See rationale in std::collections::BtreeMap docs.
-                Some(std::mem::replace(&mut self.val, val))
+        while let Some(n) = node {
+            if after_start(n.key.borrow(), start) {
+                found = Some(n);
+                node = n.left.as_deref();
+            } else {
+                node = n.right.as_deref();
             }
-            Ordering::Less => {
-                let old_val = if let Some(ref mut left) = self.left {
-                    left.insert(key, val, priority)
-                } else {
-                    self.left = Some(Box::new(Node::new(key, val, priority)));
-                    None
-                };
+        }
+
+        found
+    }
+
+    /// Finds the node with the largest key that comes before `end`.
+    pub fn last_before<Q>(&self, end: Bound<&Q>) -> Option<&Self>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut node = Some(self);
+        let mut found = None;
+
+        while let Some(n) = node {
+            if before_end(n.key.borrow(), end) {
+                found = Some(n);
+                node = n.right.as_deref();
+            } else {
+                node = n.left.as_deref();
+            }
+        }
+
+        found
+    }
+
+    /// Checks that the priorities are in heap order, that the cached sizes
+    /// add up, and that `ordered` holds for every two keys next to each
+    /// other in order, walking the tree without recursion.
+    ///
+    /// Returns the number of nodes. `describe` names the key at some
+    /// position in order, for the error.
+    pub fn validate<F, D>(
+        tree: &Tree<K, V, M>,
+        mut ordered: F,
+        describe: D,
+    ) -> Result<usize, InvariantError>
+    where
+        F: FnMut(&K, &K) -> bool,
+        D: Fn(usize, &K) -> String,
+    {
+        let mut stack = Vec::new();
+        let mut prev: Option<&K> = None;
+        let mut index = 0;
+        let mut node = tree.as_deref();
+
+        loop {
+            while let Some(n) = node {
+                stack.push(n);
+                node = n.left.as_deref();
+            }
+            let Some(n) = stack.pop() else {
+                return Ok(index);
+            };
 
-                if self.is_heap_property_violated(&self.left) {
-                    self.rotate_right();
+            if let Some(prev) = prev.filter(|prev| !ordered(prev, &n.key)) {
+                return Err(InvariantError::Order {
+                    before: describe(index - 1, prev),
+                    after: describe(index, &n.key),
+                });
+            }
+
+            let children = [
+                n.left
+                    .as_deref()
+                    .map(|c| (c, index.wrapping_sub(1 + Self::size(&c.right)))),
+                n.right
+                    .as_deref()
+                    .map(|c| (c, index + 1 + Self::size(&c.left))),
+            ];
+            for (child, position) in children.into_iter().flatten() {
+                if child.priority > n.priority {
+                    return Err(InvariantError::Heap {
+                        parent: describe(index, &n.key),
+                        child: describe(position, &child.key),
+                    });
                 }
+            }
 
-                old_val
+            let actual = 1 + Self::size(&n.left) + Self::size(&n.right);
+            if n.size != actual {
+                return Err(InvariantError::Size {
+                    key: describe(index, &n.key),
+                    cached: n.size,
+                    actual,
+                });
             }
-            Ordering::Greater => {
-                let old_val = if let Some(ref mut right) = self.right {
-                    right.insert(key, val, priority)
-                } else {
-                    self.right = Some(Box::new(Node::new(key, val, priority)));
-                    None
-                };
 
-                // TODO: restore heap invariant
-                if self.is_heap_property_violated(&self.right) {
-                    self.rotate_left();
+            prev = Some(&n.key);
+            index += 1;
+            node = n.right.as_deref();
+        }
+    }
+}
+
+// Handing out mutable values would let them drift from the folds cached
+// above them, so only nodes without a fold get to do it.
+impl<K, V> Node<K, V> {
+    pub fn val_mut(&mut self) -> &mut V {
+        &mut self.val
+    }
+
+    pub fn key_val_mut(&mut self) -> (&K, &mut V) {
+        (&self.key, &mut self.val)
+    }
+
+    pub fn select_mut(&mut self, mut index: usize) -> Option<&mut Self> {
+        let mut node = self;
+
+        loop {
+            node.push_down();
+            let left = Self::size(&node.left);
+
+            match index.cmp(&left) {
+                Ordering::Equal => return Some(node),
+                Ordering::Less => node = node.left.as_deref_mut()?,
+                Ordering::Greater => {
+                    index -= left + 1;
+                    node = node.right.as_deref_mut()?;
                 }
+            }
+        }
+    }
+}
+
+// Dropping a subtree one node at a time, so that deep trees don't overflow
+// the stack the way the recursive drop glue of `Box<Node>` would.
+impl<K, V, M: Monoid<V>, A: Action<V, M>> Drop for Node<K, V, M, A> {
+    fn drop(&mut self) {
+        if self.left.is_none() && self.right.is_none() {
+            return;
+        }
+
+        let mut stack: Vec<_> = self
+            .left
+            .take()
+            .into_iter()
+            .chain(self.right.take())
+            .collect();
+
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.left.take());
+            stack.extend(node.right.take());
+        }
+    }
+}
+
+/// What a set operation makes of a pair of trees: either the result right
+/// away, or a root and two smaller pairs whose results go below it.
+enum Divide<T, S, R> {
+    Done(R),
+    Split(T, S, S),
+}
+
+/// Runs a divide and conquer over the pairs of trees of a set operation,
+/// keeping the pending pairs and results in vectors instead of on the call
+/// stack, which the depth of the trees could overflow.
+///
+/// `split` is called on the left pair before the right one, and `join` on
+/// a root once both of its results are in.
+fn divide<S, T, R>(
+    problem: S,
+    mut split: impl FnMut(S) -> Divide<T, S, R>,
+    mut join: impl FnMut(T, R, R) -> R,
+) -> R {
+    enum Task<S, T> {
+        Solve(S),
+        Join(T),
+    }
+
+    let mut tasks = vec![Task::Solve(problem)];
+    let mut results = Vec::new();
 
-                old_val
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Solve(problem) => match split(problem) {
+                Divide::Done(result) => results.push(result),
+                Divide::Split(root, left, right) => {
+                    tasks.push(Task::Join(root));
+                    tasks.push(Task::Solve(right));
+                    tasks.push(Task::Solve(left));
+                }
+            },
+            Task::Join(root) => {
+                let right = results.pop().expect("a joined root has a right result");
+                let left = results.pop().expect("a joined root has a left result");
+                results.push(join(root, left, right));
             }
         }
     }
 
-    fn is_heap_property_violated(&self, subtree: &Option<Box<Node<K, V>>>) -> bool {
-        if let Some(child) = subtree.as_ref() {
-            self.priority < child.priority
-        } else {
-            false
+    results.pop().expect("the whole problem has a result")
+}
+
+/// Builds a tree out of entries pushed in ascending key order, in amortized
+/// O(1) per entry, the way a Cartesian tree is built from a sequence.
+///
+/// Only the right spine of the tree built so far is kept, top down. A new
+/// entry pops every spine node of lower priority, which then become its left
+/// subtree, and goes at the bottom of the spine.
+pub(crate) struct Spine<K, V, M: Monoid<V> = (), A: Action<V, M> = ()> {
+    nodes: Vec<Box<Node<K, V, M, A>>>,
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>> Spine<K, V, M, A> {
+    pub fn new() -> Self {
+        Self { nodes: Vec::new() }
+    }
+
+    /// Returns the key pushed last, which every new key has to exceed.
+    pub fn last_key(&self) -> Option<&K> {
+        self.nodes.last().map(|node| &node.key)
+    }
+
+    pub fn push(&mut self, key: K, val: V, priority: u64) {
+        let mut node = Node::boxed(key, val, priority);
+        node.left = self.pop_while(|top| top.priority < priority);
+
+        self.nodes.push(node);
+    }
+
+    pub fn finish(mut self) -> Tree<K, V, M, A> {
+        self.pop_while(|_| true)
+    }
+
+    /// Pops spine nodes while `pred` holds, hanging each one on the right of
+    /// the next, and returns the topmost of them.
+    fn pop_while<F>(&mut self, mut pred: F) -> Tree<K, V, M, A>
+    where
+        F: FnMut(&Node<K, V, M, A>) -> bool,
+    {
+        let mut below = None;
+
+        while self.nodes.last().is_some_and(|top| pred(top)) {
+            let mut top = self.nodes.pop()?;
+            top.right = below;
+            top.update();
+            below = Some(top);
         }
+
+        below
     }
+}
 
-    //        y             x
-    //       / \           / \
-    //      x   c -->    a    y
-    //    / \                / \
-    //  a    b              b   c
-    fn rotate_right(&mut self) {
-        use std::mem;
+/// Rebuilds a tree out of its nodes pushed in preorder, each one saying
+/// which children it has, without comparing keys or rotating anything.
+///
+/// Nodes whose subtrees are still being pushed are kept on a stack, with the
+/// children each one is still waiting for. A node is hung below its parent
+/// once its last child is in.
+pub(crate) struct Preorder<K, V, M: Monoid<V> = (), A: Action<V, M> = ()> {
+    open: Vec<Open<K, V, M, A>>,
+    root: Tree<K, V, M, A>,
+}
 
-        let x = self.left.take();
+struct Open<K, V, M: Monoid<V>, A: Action<V, M>> {
+    node: Box<Node<K, V, M, A>>,
+    left: bool,
+    right: bool,
+}
 
-        if let Some(mut x) = x {
-            mem::swap(self, &mut x);
-            mem::swap(&mut self.right, &mut x.left);
-            let _ = mem::replace(&mut self.right, Some(x));
+impl<K, V, M: Monoid<V>, A: Action<V, M>> Preorder<K, V, M, A> {
+    pub fn new() -> Self {
+        Self {
+            open: Vec::new(),
+            root: None,
         }
     }
 
-    //        y             x
-    //       / \           / \
-    //      a   x -->    y    c
-    //         / \      / \
-    //        b   c    a   b
-    fn rotate_left(&mut self) {
-        use std::mem;
+    /// Pushes the next node, returning `false` if the tree was already
+    /// complete.
+    pub fn push(&mut self, key: K, val: V, priority: u64, left: bool, right: bool) -> bool {
+        if self.open.is_empty() && self.root.is_some() {
+            return false;
+        }
 
-        let x = self.right.take();
+        let node = Node::boxed(key, val, priority);
+        self.open.push(Open { node, left, right });
 
-        if let Some(mut x) = x {
-            mem::swap(self, &mut x);
-            mem::swap(&mut self.left, &mut x.right);
-            let _ = mem::replace(&mut self.left, Some(x));
+        while self.open.last().is_some_and(|top| !top.left && !top.right) {
+            let Some(Open { mut node, .. }) = self.open.pop() else {
+                break;
+            };
+            node.update();
+
+            match self.open.last_mut() {
+                Some(parent) if parent.left => {
+                    parent.node.left = Some(node);
+                    parent.left = false;
+                }
+                Some(parent) => {
+                    parent.node.right = Some(node);
+                    parent.right = false;
+                }
+                None => self.root = Some(node),
+            }
+        }
+
+        true
+    }
+
+    /// Returns the tree, or `None` if some node is still missing a child.
+    pub fn finish(self) -> Option<Tree<K, V, M, A>> {
+        self.open.is_empty().then_some(self.root)
+    }
+}
+
+/// A handle to a subtree that can be taken apart into its left child, its
+/// own entry and its right child.
+pub(crate) trait Split: Sized {
+    type Key;
+    type Entry;
+
+    fn key(&self) -> &Self::Key;
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>);
+}
+
+impl<'a, K, V, M: Monoid<V>> Split for &'a Node<K, V, M> {
+    type Key = K;
+    type Entry = (&'a K, &'a V);
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        // maps never reverse anything, sequences walk through `Oriented`
+        debug_assert!(!self.reversed);
+
+        (
+            self.left.as_deref(),
+            (&self.key, &self.val),
+            self.right.as_deref(),
+        )
+    }
+}
+
+impl<'a, K, V> Split for &'a mut Node<K, V> {
+    type Key = K;
+    type Entry = (&'a K, &'a mut V);
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        self.push_down();
+
+        (
+            self.left.as_deref_mut(),
+            (&self.key, &mut self.val),
+            self.right.as_deref_mut(),
+        )
+    }
+}
+
+impl<K, V, M: Monoid<V>, A: Action<V, M>> Split for Box<Node<K, V, M, A>> {
+    type Key = K;
+    type Entry = (K, V);
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn split(mut self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        self.push_down();
+        let left = self.left.take();
+        let right = self.right.take();
+
+        (left, (*self).into_entry(), right)
+    }
+}
+
+/// A mutable handle to a subtree that settles pending actions on its way
+/// down, but only hands out shared references to the values, as their folds
+/// are cached in the nodes above.
+pub(crate) struct Settled<'a, K, V, M: Monoid<V>, A: Action<V, M>>(&'a mut Node<K, V, M, A>);
+
+impl<'a, K, V, M: Monoid<V>, A: Action<V, M>> Settled<'a, K, V, M, A> {
+    pub fn new(root: &'a mut Node<K, V, M, A>) -> Self {
+        Self(root)
+    }
+}
+
+impl<'a, K, V, M: Monoid<V>, A: Action<V, M>> Split for Settled<'a, K, V, M, A> {
+    type Key = K;
+    type Entry = (&'a K, &'a V);
+
+    fn key(&self) -> &K {
+        &self.0.key
+    }
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        let node = self.0;
+        node.push_down();
+
+        (
+            node.left.as_deref_mut().map(Settled),
+            (&node.key, &node.val),
+            node.right.as_deref_mut().map(Settled),
+        )
+    }
+}
+
+/// A shared handle to a subtree that keeps track of the reversals pending
+/// above it, which a plain `&Node` has no way to see.
+pub(crate) struct Oriented<'a, K, V, M: Monoid<V> = ()> {
+    node: &'a Node<K, V, M>,
+    flipped: bool,
+}
+
+impl<'a, K, V, M: Monoid<V>> Oriented<'a, K, V, M> {
+    pub fn new(root: &'a Node<K, V, M>) -> Self {
+        Self {
+            node: root,
+            flipped: false,
+        }
+    }
+}
+
+impl<K, V, M: Monoid<V>> Clone for Oriented<'_, K, V, M> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V, M: Monoid<V>> Copy for Oriented<'_, K, V, M> {}
+
+impl<'a, K, V, M: Monoid<V>> Split for Oriented<'a, K, V, M> {
+    type Key = K;
+    type Entry = (&'a K, &'a V);
+
+    fn key(&self) -> &K {
+        &self.node.key
+    }
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        let flipped = self.flipped ^ self.node.reversed;
+        let (left, right) = self.node.children(flipped);
+        let oriented = |node| Self { node, flipped };
+
+        (
+            left.map(oriented),
+            (&self.node.key, &self.node.val),
+            right.map(oriented),
+        )
+    }
+}
+
+pub(crate) enum Step<T: Split> {
+    Entry(T::Entry),
+    Tree(T),
+}
+
+impl<T> Clone for Step<T>
+where
+    T: Split + Clone,
+    T::Entry: Clone,
+{
+    fn clone(&self) -> Self {
+        match self {
+            Step::Entry(entry) => Step::Entry(entry.clone()),
+            Step::Tree(tree) => Step::Tree(tree.clone()),
+        }
+    }
+}
+
+/// An in-order walk that can be advanced from both ends.
+///
+/// The queue holds what is left of the traversal: entries interleaved with
+/// subtrees that have not been looked at yet. Subtrees are only taken apart
+/// once they reach one of the ends, so the queue stays O(height) and the
+/// borrows handed out from the front and the back never overlap.
+pub(crate) struct Walk<T: Split> {
+    queue: VecDeque<Step<T>>,
+}
+
+impl<T: Split> Walk<T> {
+    pub fn new(root: Option<T>) -> Self {
+        Self {
+            queue: root.map(Step::Tree).into_iter().collect(),
+        }
+    }
+
+    /// Starts a walk over the entries whose keys fall within `range`.
+    ///
+    /// Only the two boundary paths are taken apart, everything strictly
+    /// inside the range stays queued as whole subtrees.
+    pub fn range<Q, R>(root: Option<T>, range: &R) -> Self
+    where
+        T::Key: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let start = range.start_bound();
+        let end = range.end_bound();
+        let mut queue = VecDeque::new();
+
+        // find the topmost node inside the range, both boundary paths fork
+        // off from there
+        let mut tree = root;
+        let (left, right) = loop {
+            let Some(node) = tree else {
+                return Self { queue };
+            };
+
+            if !after_start(node.key().borrow(), start) {
+                tree = node.split().2;
+            } else if !before_end(node.key().borrow(), end) {
+                tree = node.split().0;
+            } else {
+                let (left, entry, right) = node.split();
+                queue.push_back(Step::Entry(entry));
+                break (left, right);
+            }
+        };
+
+        let mut tree = left;
+        while let Some(node) = tree {
+            if after_start(node.key().borrow(), start) {
+                let (left, entry, right) = node.split();
+                if let Some(right) = right {
+                    queue.push_front(Step::Tree(right));
+                }
+                queue.push_front(Step::Entry(entry));
+                tree = left;
+            } else {
+                tree = node.split().2;
+            }
+        }
+
+        let mut tree = right;
+        while let Some(node) = tree {
+            if before_end(node.key().borrow(), end) {
+                let (left, entry, right) = node.split();
+                if let Some(left) = left {
+                    queue.push_back(Step::Tree(left));
+                }
+                queue.push_back(Step::Entry(entry));
+                tree = right;
+            } else {
+                tree = node.split().0;
+            }
+        }
+
+        Self { queue }
+    }
+
+    pub fn next_front(&mut self) -> Option<T::Entry> {
+        loop {
+            match self.queue.pop_front()? {
+                Step::Entry(entry) => return Some(entry),
+                Step::Tree(tree) => {
+                    let (left, entry, right) = tree.split();
+
+                    if let Some(right) = right {
+                        self.queue.push_front(Step::Tree(right));
+                    }
+                    self.queue.push_front(Step::Entry(entry));
+                    if let Some(left) = left {
+                        self.queue.push_front(Step::Tree(left));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn next_back(&mut self) -> Option<T::Entry> {
+        loop {
+            match self.queue.pop_back()? {
+                Step::Entry(entry) => return Some(entry),
+                Step::Tree(tree) => {
+                    let (left, entry, right) = tree.split();
+
+                    if let Some(left) = left {
+                        self.queue.push_back(Step::Tree(left));
+                    }
+                    self.queue.push_back(Step::Entry(entry));
+                    if let Some(right) = right {
+                        self.queue.push_back(Step::Tree(right));
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn after_start<Q: Ord + ?Sized>(key: &Q, start: Bound<&Q>) -> bool {
+    match start {
+        Bound::Included(start) => key >= start,
+        Bound::Excluded(start) => key > start,
+        Bound::Unbounded => true,
+    }
+}
+
+pub(crate) fn before_end<Q: Ord + ?Sized>(key: &Q, end: Bound<&Q>) -> bool {
+    match end {
+        Bound::Included(end) => key <= end,
+        Bound::Excluded(end) => key < end,
+        Bound::Unbounded => true,
+    }
+}
+
+impl<T> Clone for Walk<T>
+where
+    T: Split + Clone,
+    T::Entry: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::rc::Rc;
+
     use super::*;
 
+    #[test]
+    fn validate_names_broken_nodes() {
+        let describe = |_, key: &u8| (*key as char).to_string();
+        let check = |tree: &Tree<u8, ()>| Node::validate(tree, |a, b| a < b, describe);
+
+        let mut root: Box<Node<u8, ()>> = Box::new(Node::new(b'b', (), 10));
+        root.left = Some(Box::new(Node::new(b'a', (), 5)));
+        root.right = Some(Box::new(Node::new(b'c', (), 5)));
+        root.update();
+        let mut tree = Some(root);
+        assert_eq!(check(&tree), Ok(3));
+
+        let root = tree.as_mut().unwrap();
+        root.right.as_mut().unwrap().key = b'a';
+        assert_eq!(
+            check(&tree),
+            Err(InvariantError::Order {
+                before: "b".into(),
+                after: "a".into()
+            })
+        );
+
+        let root = tree.as_mut().unwrap();
+        let right = root.right.as_mut().unwrap();
+        right.key = b'c';
+        right.priority = 20;
+        assert_eq!(
+            check(&tree),
+            Err(InvariantError::Heap {
+                parent: "b".into(),
+                child: "c".into()
+            })
+        );
+
+        let root = tree.as_mut().unwrap();
+        root.right.as_mut().unwrap().priority = 5;
+        root.size = 5;
+        assert_eq!(
+            check(&tree),
+            Err(InvariantError::Size {
+                key: "b".into(),
+                cached: 5,
+                actual: 3
+            })
+        );
+    }
+
     #[test]
     fn rotate_right() {
         let a = Box::new(Node {
@@ -134,6 +1702,11 @@ mod tests {
             left: None,
             right: None,
             priority: 0,
+            size: 1,
+            reversed: false,
+            agg: (),
+            tag: None,
+            stamp: 0,
         });
         let b = Box::new(Node {
             key: b'b',
@@ -141,6 +1714,11 @@ mod tests {
             left: None,
             right: None,
             priority: 0,
+            size: 1,
+            reversed: false,
+            agg: (),
+            tag: None,
+            stamp: 0,
         });
         let c = Box::new(Node {
             key: b'c',
@@ -148,6 +1726,11 @@ mod tests {
             left: None,
             right: None,
             priority: 0,
+            size: 1,
+            reversed: false,
+            agg: (),
+            tag: None,
+            stamp: 0,
         });
 
         let x = Box::new(Node {
@@ -156,27 +1739,39 @@ mod tests {
             left: Some(a),
             right: Some(b),
             priority: 0,
+            size: 3,
+            reversed: false,
+            agg: (),
+            tag: None,
+            stamp: 0,
         });
 
-        let mut y = Box::new(Node {
+        let mut y: Box<Node<u8, ()>> = Box::new(Node {
             key: b'y',
             val: (),
             priority: 0,
             left: Some(x),
             right: Some(c),
+            size: 5,
+            reversed: false,
+            agg: (),
+            tag: None,
+            stamp: 0,
         });
 
         y.rotate_right();
 
         assert_eq!(y.key, b'x');
-        assert_eq!(y.left.unwrap().key, b'a');
+        assert_eq!(y.size, 5);
+        assert_eq!(y.right.as_ref().unwrap().size, 3);
+        assert_eq!(y.left.as_ref().unwrap().key, b'a');
 
         {
-            let y = y.right.unwrap();
+            let y = y.right.as_ref().unwrap();
             assert_eq!(y.key, b'y');
 
-            assert_eq!(y.left.unwrap().key, b'b');
-            assert_eq!(y.right.unwrap().key, b'c');
+            assert_eq!(y.left.as_ref().unwrap().key, b'b');
+            assert_eq!(y.right.as_ref().unwrap().key, b'c');
         }
     }
 
@@ -188,6 +1783,11 @@ mod tests {
             left: None,
             right: None,
             priority: 0,
+            size: 1,
+            reversed: false,
+            agg: (),
+            tag: None,
+            stamp: 0,
         });
         let b = Box::new(Node {
             key: b'b',
@@ -195,6 +1795,11 @@ mod tests {
             left: None,
             right: None,
             priority: 0,
+            size: 1,
+            reversed: false,
+            agg: (),
+            tag: None,
+            stamp: 0,
         });
         let c = Box::new(Node {
             key: b'c',
@@ -202,6 +1807,11 @@ mod tests {
             left: None,
             right: None,
             priority: 0,
+            size: 1,
+            reversed: false,
+            agg: (),
+            tag: None,
+            stamp: 0,
         });
 
         let x = Box::new(Node {
@@ -210,27 +1820,101 @@ mod tests {
             left: Some(b),
             right: Some(c),
             priority: 0,
+            size: 3,
+            reversed: false,
+            agg: (),
+            tag: None,
+            stamp: 0,
         });
 
-        let mut y = Box::new(Node {
+        let mut y: Box<Node<u8, ()>> = Box::new(Node {
             key: b'y',
             val: (),
             priority: 0,
             left: Some(a),
             right: Some(x),
+            size: 5,
+            reversed: false,
+            agg: (),
+            tag: None,
+            stamp: 0,
         });
 
         y.rotate_left();
 
         assert_eq!(y.key, b'x');
-        assert_eq!(y.right.unwrap().key, b'c');
+        assert_eq!(y.size, 5);
+        assert_eq!(y.left.as_ref().unwrap().size, 3);
+        assert_eq!(y.right.as_ref().unwrap().key, b'c');
 
         {
-            let y = y.left.unwrap();
+            let y = y.left.as_ref().unwrap();
             assert_eq!(y.key, b'y');
 
-            assert_eq!(y.left.unwrap().key, b'a');
-            assert_eq!(y.right.unwrap().key, b'b');
+            assert_eq!(y.left.as_ref().unwrap().key, b'a');
+            assert_eq!(y.right.as_ref().unwrap().key, b'b');
+        }
+    }
+
+    #[test]
+    fn rotations_apply_pending_reversals() {
+        let mut tree = None;
+        for (i, key) in (b'a'..=b'g').enumerate() {
+            // priorities peak in the middle, so `d` ends up at the root
+            let priority = 4 - i.abs_diff(3) as u64;
+            let path = Node::search_gap(&mut tree, i);
+            unsafe { Node::attach(&mut tree, &path, Box::new(Node::new(key, (), priority))) };
+        }
+
+        let keys = |tree: &Tree<u8, ()>| {
+            let mut walk = Walk::new(tree.as_deref().map(Oriented::new));
+            std::iter::from_fn(|| walk.next_front().map(|(&k, _)| k)).collect::<Vec<_>>()
+        };
+        assert_eq!(keys(&tree), b"abcdefg");
+
+        let root = tree.as_mut().unwrap();
+        root.reverse();
+        root.left.as_mut().unwrap().reverse();
+        assert_eq!(keys(&tree), b"gfedabc");
+
+        tree.as_mut().unwrap().rotate_right();
+        assert!(!tree.as_ref().unwrap().reversed);
+        assert_eq!(keys(&tree), b"gfedabc");
+
+        tree.as_mut().unwrap().rotate_left();
+        tree.as_mut().unwrap().rotate_left();
+        assert_eq!(keys(&tree), b"gfedabc");
+        assert_eq!(tree.as_ref().unwrap().size, 7);
+    }
+
+    /// Keeps a clone of some value in the fold, to tell when it's dropped.
+    struct Holds;
+
+    impl Monoid<Rc<()>> for Holds {
+        type Agg = Option<Rc<()>>;
+
+        fn identity() -> Self::Agg {
+            None
         }
+
+        fn lift(val: &Rc<()>) -> Self::Agg {
+            Some(val.clone())
+        }
+
+        fn combine(left: &Self::Agg, right: &Self::Agg) -> Self::Agg {
+            left.clone().or_else(|| right.clone())
+        }
+    }
+
+    #[test]
+    fn into_entry_drops_the_fold() {
+        let val = Rc::new(());
+        let node: Node<u8, Rc<()>, Holds> = Node::new(0, val.clone(), 0);
+        assert_eq!(Rc::strong_count(&val), 3);
+
+        let (_, moved) = node.into_entry();
+        assert_eq!(Rc::strong_count(&val), 2);
+        drop(moved);
+        assert_eq!(Rc::strong_count(&val), 1);
     }
 }
@@ -0,0 +1,544 @@
+use std::{
+    borrow::Borrow, cmp::Ordering, fmt, iter::FusedIterator, mem, ops::RangeBounds, sync::Arc,
+};
+
+use rand::RngCore;
+
+use crate::{
+    map::check_range,
+    node::{Split, Walk},
+    priority::{DefaultPriority, PrioritySource},
+};
+
+type Link<K, V> = Option<Arc<Node<K, V>>>;
+
+#[derive(Clone)]
+struct Node<K, V> {
+    key: K,
+    val: V,
+    left: Link<K, V>,
+    right: Link<K, V>,
+    priority: u64,
+}
+
+/// An ordered map whose versions share structure, so that cloning it takes
+/// O(1).
+///
+/// Nodes live behind [`Arc`]s and are never modified while shared:
+/// insertions and removals copy the nodes on the path they walk, rotations
+/// included, and leave every other subtree shared with the older versions.
+/// A snapshot is `Send + Sync` whenever its keys, values and priority
+/// source are, so readers can keep an old version while a writer moves on.
+///
+/// ```
+/// use treap::persistent::PersistentTreapMap;
+///
+/// let mut map = PersistentTreapMap::new();
+/// map.insert(1, "a");
+///
+/// let snapshot = map.clone();
+/// map.insert(2, "b");
+///
+/// assert_eq!(snapshot.len(), 1);
+/// assert_eq!(map.len(), 2);
+/// ```
+pub struct PersistentTreapMap<K, V, P = DefaultPriority> {
+    root: Link<K, V>,
+    len: usize,
+    priorities: P,
+}
+
+impl<K, V> PersistentTreapMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_priorities(DefaultPriority::new())
+    }
+
+    /// Creates a map whose priorities are drawn from a generator seeded
+    /// with `seed`, so that the same operations always build the same tree.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_priorities(DefaultPriority::with_seed(seed))
+    }
+}
+
+impl<K, V, R: RngCore> PersistentTreapMap<K, V, R> {
+    /// Creates a map whose priorities are drawn from `rng`.
+    pub fn with_rng(rng: R) -> Self {
+        Self::with_priorities(rng)
+    }
+}
+
+impl<K, V, P> PersistentTreapMap<K, V, P> {
+    pub fn with_priorities(priorities: P) -> Self {
+        Self {
+            root: None,
+            len: 0,
+            priorities,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let mut node = self.root.as_deref();
+
+        while let Some(n) = node {
+            match key.cmp(n.key.borrow()) {
+                Ordering::Equal => return Some(&n.val),
+                Ordering::Less => node = n.left.as_deref(),
+                Ordering::Greater => node = n.right.as_deref(),
+            }
+        }
+
+        None
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Inserts an entry, copying the nodes on the path down to it and any
+    /// node rotated on the way back up that is shared with another version.
+    pub fn insert(&mut self, key: K, val: V) -> Option<V>
+    where
+        K: Ord + Clone,
+        V: Clone,
+        P: PrioritySource,
+    {
+        let priority = self.priorities.next_priority();
+        let res = Node::insert(&mut self.root, key, val, priority);
+
+        if res.is_none() {
+            self.len += 1;
+        }
+
+        res
+    }
+
+    /// Removes an entry, copying the nodes on the path down to it and along
+    /// the inner spines of its subtrees, which are joined in its place.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Ord + Clone,
+        Q: Ord + ?Sized,
+        V: Clone,
+    {
+        // don't copy a path that leads nowhere
+        if !self.contains_key(key) {
+            return None;
+        }
+
+        let res = Node::remove(&mut self.root, key);
+        if res.is_some() {
+            self.len -= 1;
+        }
+
+        res
+    }
+
+    /// Iterates over the entries of the map, sorted by key.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            walk: Walk::new(self.root.as_deref()),
+            len: self.len,
+        }
+    }
+
+    /// Iterates over the entries whose keys fall within `range`, sorted by
+    /// key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end, or if both
+    /// ends are equal and excluded.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        check_range(&range);
+
+        Range {
+            walk: Walk::range(self.root.as_deref(), &range),
+        }
+    }
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: K, val: V, priority: u64) -> Self {
+        Self {
+            key,
+            val,
+            left: None,
+            right: None,
+            priority,
+        }
+    }
+
+    fn priority(link: &Link<K, V>) -> Option<u64> {
+        link.as_ref().map(|node| node.priority)
+    }
+}
+
+impl<K: Clone, V: Clone> Node<K, V> {
+    fn insert(link: &mut Link<K, V>, key: K, val: V, priority: u64) -> Option<V>
+    where
+        K: Ord,
+    {
+        let Some(node) = link else {
+            *link = Some(Arc::new(Node::new(key, val, priority)));
+            return None;
+        };
+
+        let node = Arc::make_mut(node);
+        match key.cmp(&node.key) {
+            Ordering::Equal => {
+                // like the mutable map, keep the key and the higher priority,
+                // the caller rotates us up if that breaks the heap order
+                node.priority = node.priority.max(priority);
+                Some(mem::replace(&mut node.val, val))
+            }
+            Ordering::Less => {
+                let res = Self::insert(&mut node.left, key, val, priority);
+                if Self::priority(&node.left) > Some(node.priority) {
+                    Self::rotate_right(link);
+                }
+
+                res
+            }
+            Ordering::Greater => {
+                let res = Self::insert(&mut node.right, key, val, priority);
+                if Self::priority(&node.right) > Some(node.priority) {
+                    Self::rotate_left(link);
+                }
+
+                res
+            }
+        }
+    }
+
+    fn remove<Q>(link: &mut Link<K, V>, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let node = Arc::make_mut(link.as_mut()?);
+
+        match key.cmp(node.key.borrow()) {
+            Ordering::Less => Self::remove(&mut node.left, key),
+            Ordering::Greater => Self::remove(&mut node.right, key),
+            Ordering::Equal => {
+                let joined = Self::merge(node.left.take(), node.right.take());
+                let removed = mem::replace(link, joined)?;
+
+                // `make_mut` left us as the only owner
+                Some(match Arc::try_unwrap(removed) {
+                    Ok(node) => node.val,
+                    Err(node) => node.val.clone(),
+                })
+            }
+        }
+    }
+
+    fn merge(left: Link<K, V>, right: Link<K, V>) -> Link<K, V> {
+        match (left, right) {
+            (None, tree) | (tree, None) => tree,
+            (Some(mut left), Some(mut right)) => {
+                if left.priority > right.priority {
+                    let node = Arc::make_mut(&mut left);
+                    node.right = Self::merge(node.right.take(), Some(right));
+                    Some(left)
+                } else {
+                    let node = Arc::make_mut(&mut right);
+                    node.left = Self::merge(Some(left), node.left.take());
+                    Some(right)
+                }
+            }
+        }
+    }
+
+    //        y             x
+    //       / \           / \
+    //      x   c -->    a    y
+    //    / \                / \
+    //  a    b              b   c
+    fn rotate_right(link: &mut Link<K, V>) {
+        let Some(mut y) = link.take() else {
+            return;
+        };
+
+        let y_node = Arc::make_mut(&mut y);
+        let Some(mut x) = y_node.left.take() else {
+            *link = Some(y);
+            return;
+        };
+
+        let x_node = Arc::make_mut(&mut x);
+        y_node.left = x_node.right.take();
+        x_node.right = Some(y);
+        *link = Some(x);
+    }
+
+    //        y             x
+    //       / \           / \
+    //      a   x -->    y    c
+    //         / \      / \
+    //        b   c    a   b
+    fn rotate_left(link: &mut Link<K, V>) {
+        let Some(mut y) = link.take() else {
+            return;
+        };
+
+        let y_node = Arc::make_mut(&mut y);
+        let Some(mut x) = y_node.right.take() else {
+            *link = Some(y);
+            return;
+        };
+
+        let x_node = Arc::make_mut(&mut x);
+        y_node.right = x_node.left.take();
+        x_node.left = Some(y);
+        *link = Some(x);
+    }
+}
+
+// Only the nodes no other version still holds are taken apart, one at a
+// time, so that deep trees don't overflow the stack.
+impl<K, V, P> Drop for PersistentTreapMap<K, V, P> {
+    fn drop(&mut self) {
+        let mut stack: Vec<_> = self.root.take().into_iter().collect();
+
+        while let Some(node) = stack.pop() {
+            if let Some(mut node) = Arc::into_inner(node) {
+                stack.extend(node.left.take());
+                stack.extend(node.right.take());
+            }
+        }
+    }
+}
+
+/// Takes O(1): the new map shares every node with this one.
+impl<K, V, P: Clone> Clone for PersistentTreapMap<K, V, P> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            len: self.len,
+            priorities: self.priorities.clone(),
+        }
+    }
+}
+
+impl<K, V, P: Default> Default for PersistentTreapMap<K, V, P> {
+    fn default() -> Self {
+        Self::with_priorities(P::default())
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, P> fmt::Debug for PersistentTreapMap<K, V, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K, V, P> IntoIterator for &'a PersistentTreapMap<K, V, P> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K, V> Split for &'a Node<K, V> {
+    type Key = K;
+    type Entry = (&'a K, &'a V);
+
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        (
+            self.left.as_deref(),
+            (&self.key, &self.val),
+            self.right.as_deref(),
+        )
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    walk: Walk<&'a Node<K, V>>,
+    len: usize,
+}
+
+impl<K, V> Clone for Iter<'_, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            walk: self.walk.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.walk.next_front()?;
+        self.len -= 1;
+
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let entry = self.walk.next_back()?;
+        self.len -= 1;
+
+        Some(entry)
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+impl<K, V> FusedIterator for Iter<'_, K, V> {}
+
+pub struct Range<'a, K, V> {
+    walk: Walk<&'a Node<K, V>>,
+}
+
+impl<K, V> Clone for Range<'_, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            walk: self.walk.clone(),
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walk.next_front()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Range<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.walk.next_back()
+    }
+}
+
+impl<K, V> FusedIterator for Range<'_, K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn snapshots_are_independent() {
+        let mut rng = StdRng::seed_from_u64(15);
+        let mut map = PersistentTreapMap::with_seed(15);
+        let mut expected = BTreeMap::new();
+        let mut snapshots = Vec::new();
+
+        for i in 0..2000 {
+            let key = rng.gen_range(0..500);
+
+            if rng.gen_bool(0.3) {
+                assert_eq!(map.remove(&key), expected.remove(&key));
+            } else {
+                assert_eq!(map.insert(key, i), expected.insert(key, i));
+            }
+
+            if i % 100 == 0 {
+                snapshots.push((map.clone(), expected.clone()));
+            }
+        }
+
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter().eq(expected.iter()));
+        for (snapshot, expected) in &snapshots {
+            assert_eq!(snapshot.len(), expected.len());
+            assert!(snapshot.iter().eq(expected.iter()));
+            assert!(snapshot.iter().rev().eq(expected.iter().rev()));
+            assert!(snapshot.range(100..200).eq(expected.range(100..200)));
+        }
+    }
+
+    #[test]
+    fn writes_share_untouched_subtrees() {
+        let mut map = PersistentTreapMap::with_seed(1);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+
+        let snapshot = map.clone();
+        map.insert(1000, 1000);
+
+        let (old, new) = (snapshot.root.as_ref().unwrap(), map.root.as_ref().unwrap());
+        let shared = |a: &Link<_, _>, b: &Link<_, _>| match (a, b) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        };
+
+        // the new key goes to the far right, so the root is copied and its
+        // left subtree is not
+        assert!(!Arc::ptr_eq(old, new));
+        assert!(shared(&old.left, &new.left));
+        assert_eq!(snapshot.get(&1000), None);
+        assert_eq!(map.get(&1000), Some(&1000));
+    }
+
+    #[test]
+    fn deep_tree_drops() {
+        #[derive(Clone)]
+        struct Ascending(u64);
+
+        impl PrioritySource for Ascending {
+            fn next_priority(&mut self) -> u64 {
+                self.0 += 1;
+                self.0
+            }
+        }
+
+        // every key rotates up to the root, leaving a left spine
+        let mut map = PersistentTreapMap::with_priorities(Ascending(0));
+        for i in 0..100_000 {
+            map.insert(i, ());
+        }
+
+        let snapshot = map.clone();
+        drop(map);
+        assert_eq!(snapshot.len(), 100_000);
+    }
+
+    #[test]
+    fn send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<PersistentTreapMap<String, Vec<u8>>>();
+    }
+}
@@ -0,0 +1,115 @@
+use rand::{RngCore, SeedableRng};
+
+/// Hands out the random priorities that keep a treap balanced.
+///
+/// Every [`RngCore`] is a priority source, so any `rand` generator can be
+/// plugged in with [`TreapMap::with_rng`](crate::map::TreapMap::with_rng).
+pub trait PrioritySource {
+    fn next_priority(&mut self) -> u64;
+}
+
+impl<R: RngCore> PrioritySource for R {
+    fn next_priority(&mut self) -> u64 {
+        self.next_u64()
+    }
+}
+
+/// A priority source that can split off a new, independent one, for the
+/// half a map hands out when it is split.
+///
+/// A clone would draw the same priorities as the original from then on, so
+/// that the entries inserted into both halves would line up.
+pub trait Fork: PrioritySource {
+    fn fork(&mut self) -> Self;
+}
+
+/// Seedable generators fork by seeding the new generator from their own
+/// output.
+impl<R: RngCore + SeedableRng> Fork for R {
+    fn fork(&mut self) -> Self {
+        let mut seed = R::Seed::default();
+        self.fill_bytes(seed.as_mut());
+
+        R::from_seed(seed)
+    }
+}
+
+/// The priority source used by default: a SplitMix64 generator.
+///
+/// It is tiny, fast, and fully determined by its seed, so a map built with
+/// [`DefaultPriority::with_seed`] always ends up with the same shape for the
+/// same sequence of operations.
+#[derive(Clone, Debug)]
+pub struct DefaultPriority {
+    state: u64,
+}
+
+impl DefaultPriority {
+    /// Creates a generator seeded from the thread-local RNG.
+    pub fn new() -> Self {
+        Self::with_seed(rand::random())
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Self { state: seed }
+    }
+}
+
+impl Default for DefaultPriority {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrioritySource for DefaultPriority {
+    fn next_priority(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl Fork for DefaultPriority {
+    fn fork(&mut self) -> Self {
+        Self::with_seed(self.next_priority())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_is_deterministic() {
+        let mut a = DefaultPriority::with_seed(42);
+        let mut b = DefaultPriority::with_seed(42);
+
+        for _ in 0..100 {
+            assert_eq!(a.next_priority(), b.next_priority());
+        }
+    }
+
+    #[test]
+    fn forks_are_independent() {
+        let mut a = DefaultPriority::with_seed(42);
+        let mut b = a.fork();
+        assert!((0..100).all(|_| a.next_priority() != b.next_priority()));
+
+        // but just as deterministic
+        let mut c = DefaultPriority::with_seed(42).fork();
+        let mut d = DefaultPriority::with_seed(42).fork();
+        assert!((0..100).all(|_| c.next_priority() == d.next_priority()));
+    }
+
+    #[test]
+    fn splitmix_reference() {
+        // first outputs of the reference implementation seeded with 0
+        let mut p = DefaultPriority::with_seed(0);
+
+        assert_eq!(p.next_priority(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(p.next_priority(), 0x6e78_9e6a_a1b9_65f4);
+    }
+}
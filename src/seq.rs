@@ -0,0 +1,638 @@
+use std::{
+    fmt,
+    iter::FusedIterator,
+    ops::{Bound, Index, IndexMut, RangeBounds},
+};
+
+use rand::RngCore;
+
+use crate::{
+    node::{Node, Oriented, Walk},
+    priority::{DefaultPriority, Fork, PrioritySource},
+};
+
+/// A sequence stored as an implicit treap.
+///
+/// Nodes are ordered by position rather than by key: a node's index is the
+/// number of nodes before it, which the cached subtree sizes give us on the
+/// way down. That makes inserting, removing, splitting and concatenating
+/// anywhere in the sequence O(log n).
+type Tree<T> = Option<Box<Node<(), T>>>;
+
+pub struct TreapSeq<T, P = DefaultPriority> {
+    root: Tree<T>,
+    priorities: P,
+}
+
+impl<T> TreapSeq<T> {
+    pub fn new() -> Self {
+        Self::with_priorities(DefaultPriority::new())
+    }
+
+    /// Creates a sequence whose priorities are drawn from a generator seeded
+    /// with `seed`, so that the same operations always build the same tree.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_priorities(DefaultPriority::with_seed(seed))
+    }
+}
+
+impl<T, R: RngCore> TreapSeq<T, R> {
+    /// Creates a sequence whose priorities are drawn from `rng`.
+    pub fn with_rng(rng: R) -> Self {
+        Self::with_priorities(rng)
+    }
+}
+
+impl<T, P> TreapSeq<T, P> {
+    pub fn with_priorities(priorities: P) -> Self {
+        Self {
+            root: None,
+            priorities,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        Node::size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.root
+            .as_ref()
+            .and_then(|n| n.select(index))
+            .map(|n| n.val())
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.root
+            .as_mut()
+            .and_then(|n| n.select_mut(index))
+            .map(|n| n.val_mut())
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.root.as_ref().map(|n| n.first().val())
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.root.as_ref().map(|n| n.last().val())
+    }
+
+    /// Inserts `val` at position `index`, shifting everything after it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, val: T)
+    where
+        P: PrioritySource,
+    {
+        let len = self.len();
+        assert!(
+            index <= len,
+            "insertion index (is {index}) should be <= len (is {len})"
+        );
+
+        let leaf = Node::boxed((), val, self.priorities.next_priority());
+        let path = Node::search_gap(&mut self.root, index);
+
+        // SAFETY: `path` was just recorded on the tree and ends in an empty
+        // slot
+        unsafe { Node::attach(&mut self.root, &path, leaf) };
+    }
+
+    /// Removes and returns the element at position `index`, shifting
+    /// everything after it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> T {
+        match Node::remove_index(&mut self.root, index) {
+            Some((_, val)) => val,
+            None => panic!(
+                "removal index (is {index}) should be < len (is {})",
+                self.len()
+            ),
+        }
+    }
+
+    pub fn push_front(&mut self, val: T)
+    where
+        P: PrioritySource,
+    {
+        self.insert(0, val);
+    }
+
+    pub fn push_back(&mut self, val: T)
+    where
+        P: PrioritySource,
+    {
+        self.insert(self.len(), val);
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        Node::remove_index(&mut self.root, 0).map(|(_, val)| val)
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        let last = self.len().checked_sub(1)?;
+        Node::remove_index(&mut self.root, last).map(|(_, val)| val)
+    }
+
+    /// Splits the sequence in two, leaving `[0, at)` in `self` and returning
+    /// `[at, len)`, which gets a priority source forked from this sequence's.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at > len`.
+    pub fn split_off(&mut self, at: usize) -> Self
+    where
+        P: Fork,
+    {
+        let len = self.len();
+        assert!(
+            at <= len,
+            "`at` split index (is {at}) should be <= len (is {len})"
+        );
+
+        let (left, right) = Node::split_at(self.root.take(), at);
+        self.root = left;
+
+        Self {
+            root: right,
+            priorities: self.priorities.fork(),
+        }
+    }
+
+    /// Splits the sequence at position `at`, returning both halves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at > len`.
+    pub fn split_at(mut self, at: usize) -> (Self, Self)
+    where
+        P: Fork,
+    {
+        let right = self.split_off(at);
+        (self, right)
+    }
+
+    /// Moves every element of `other` to the end of `self` in O(log n).
+    pub fn append(&mut self, other: &mut Self) {
+        self.root = Node::merge(self.root.take(), other.root.take());
+    }
+
+    /// Joins two sequences, keeping the priority source of `self`.
+    pub fn concat(mut self, mut other: Self) -> Self {
+        self.append(&mut other);
+        self
+    }
+
+    /// Replaces the elements in `range` with those from `replace_with`,
+    /// returning the removed ones as a new sequence.
+    ///
+    /// The range is cut out with two splits, and the tail is held aside
+    /// while the replacement is pushed onto the head.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds or its start is after its end.
+    pub fn splice<R, I>(&mut self, range: R, replace_with: I) -> Self
+    where
+        R: RangeBounds<usize>,
+        I: IntoIterator<Item = T>,
+        P: Fork,
+    {
+        let (head, removed, tail) = self.cut(range);
+
+        self.root = head;
+        self.extend(replace_with);
+        self.root = Node::merge(self.root.take(), tail);
+
+        Self {
+            root: removed,
+            priorities: self.priorities.fork(),
+        }
+    }
+
+    /// Reverses the elements in `range` in O(log n).
+    ///
+    /// The range is cut out and only flagged as reversed, the flag is pushed
+    /// down a level at a time whenever later operations pass through it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds or its start is after its end.
+    pub fn reverse<R: RangeBounds<usize>>(&mut self, range: R) {
+        let (head, mut middle, tail) = self.cut(range);
+
+        if let Some(middle) = &mut middle {
+            middle.reverse();
+        }
+        self.root = Node::merge(Node::merge(head, middle), tail);
+    }
+
+    /// Rotates the elements in `range` so that the first `k` of them move to
+    /// its end, in O(log n).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds, its start is after its end, or
+    /// `k` is greater than its length.
+    pub fn rotate_left<R: RangeBounds<usize>>(&mut self, range: R, k: usize) {
+        let (head, middle, tail) = self.cut(range);
+
+        let len = Node::size(&middle);
+        if k > len {
+            self.root = Node::merge(Node::merge(head, middle), tail);
+            panic!("rotation (is {k}) should be <= range length (is {len})");
+        }
+
+        let (front, back) = Node::split_at(middle, k);
+        self.root = Node::merge(Node::merge(head, Node::merge(back, front)), tail);
+    }
+
+    /// Rotates the elements in `range` so that the last `k` of them move to
+    /// its start, in O(log n).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds, its start is after its end, or
+    /// `k` is greater than its length.
+    pub fn rotate_right<R: RangeBounds<usize>>(&mut self, range: R, k: usize) {
+        let (head, middle, tail) = self.cut(range);
+
+        let len = Node::size(&middle);
+        if k > len {
+            self.root = Node::merge(Node::merge(head, middle), tail);
+            panic!("rotation (is {k}) should be <= range length (is {len})");
+        }
+
+        let (front, back) = Node::split_at(middle, len - k);
+        self.root = Node::merge(Node::merge(head, Node::merge(back, front)), tail);
+    }
+
+    /// Takes the tree apart into the nodes before `range`, those inside it
+    /// and those after it.
+    fn cut<R: RangeBounds<usize>>(&mut self, range: R) -> (Tree<T>, Tree<T>, Tree<T>) {
+        let len = self.len();
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1).expect("range start index overflow"),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1).expect("range end index overflow"),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => len,
+        };
+        assert!(
+            start <= end,
+            "slice index starts at {start} but ends at {end}"
+        );
+        assert!(
+            end <= len,
+            "range end index {end} out of range for sequence of length {len}"
+        );
+
+        let (rest, tail) = Node::split_at(self.root.take(), end);
+        let (head, middle) = Node::split_at(rest, start);
+
+        (head, middle, tail)
+    }
+
+    /// Iterates over the elements in order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            walk: Walk::new(self.root.as_deref().map(Oriented::new)),
+            len: self.len(),
+        }
+    }
+
+    /// Iterates over the elements in order, with mutable references.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            len: self.len(),
+            walk: Walk::new(self.root.as_deref_mut()),
+        }
+    }
+}
+
+impl<T, P: Default> Default for TreapSeq<T, P> {
+    fn default() -> Self {
+        Self::with_priorities(P::default())
+    }
+}
+
+impl<T: fmt::Debug, P> fmt::Debug for TreapSeq<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// # Panics
+///
+/// Panics if the index is out of bounds.
+impl<T, P> Index<usize> for TreapSeq<T, P> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        match self.get(index) {
+            Some(val) => val,
+            None => panic!(
+                "index out of bounds: the len is {} but the index is {}",
+                self.len(),
+                index
+            ),
+        }
+    }
+}
+
+/// # Panics
+///
+/// Panics if the index is out of bounds.
+impl<T, P> IndexMut<usize> for TreapSeq<T, P> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        let len = self.len();
+
+        match self.get_mut(index) {
+            Some(val) => val,
+            None => panic!("index out of bounds: the len is {len} but the index is {index}"),
+        }
+    }
+}
+
+impl<T, P: PrioritySource + Default> FromIterator<T> for TreapSeq<T, P> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut seq = Self::default();
+        seq.extend(iter);
+
+        seq
+    }
+}
+
+impl<T, P: PrioritySource> Extend<T> for TreapSeq<T, P> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for val in iter {
+            self.push_back(val);
+        }
+    }
+}
+
+impl<'a, T, P> IntoIterator for &'a TreapSeq<T, P> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T, P> IntoIterator for &'a mut TreapSeq<T, P> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T, P> IntoIterator for TreapSeq<T, P> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            len: self.len(),
+            walk: Walk::new(self.root),
+        }
+    }
+}
+
+pub struct Iter<'a, T> {
+    walk: Walk<Oriented<'a, (), T>>,
+    len: usize,
+}
+
+impl<T> Clone for Iter<'_, T> {
+    fn clone(&self) -> Self {
+        Self {
+            walk: self.walk.clone(),
+            len: self.len,
+        }
+    }
+}
+
+pub struct IterMut<'a, T> {
+    walk: Walk<&'a mut Node<(), T>>,
+    len: usize,
+}
+
+pub struct IntoIter<T> {
+    walk: Walk<Box<Node<(), T>>>,
+    len: usize,
+}
+
+macro_rules! seq_iterator {
+    ($name:ident $(<$lt:lifetime>)?, $item:ty) => {
+        impl<$($lt,)? T> Iterator for $name<$($lt,)? T> {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                let (_, val) = self.walk.next_front()?;
+                self.len -= 1;
+
+                Some(val)
+            }
+
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                (self.len, Some(self.len))
+            }
+        }
+
+        impl<$($lt,)? T> DoubleEndedIterator for $name<$($lt,)? T> {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                let (_, val) = self.walk.next_back()?;
+                self.len -= 1;
+
+                Some(val)
+            }
+        }
+
+        impl<$($lt,)? T> ExactSizeIterator for $name<$($lt,)? T> {}
+        impl<$($lt,)? T> FusedIterator for $name<$($lt,)? T> {}
+    };
+}
+
+seq_iterator!(Iter<'a>, &'a T);
+seq_iterator!(IterMut<'a>, &'a mut T);
+seq_iterator!(IntoIter, T);
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn matches_vec() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut seq = TreapSeq::with_seed(3);
+        let mut vec = Vec::new();
+
+        for i in 0..2000 {
+            match rng.gen_range(0..4) {
+                0 | 1 => {
+                    let index = rng.gen_range(0..=vec.len());
+                    seq.insert(index, i);
+                    vec.insert(index, i);
+                }
+                2 if !vec.is_empty() => {
+                    let index = rng.gen_range(0..vec.len());
+                    assert_eq!(seq.remove(index), vec.remove(index));
+                }
+                _ if !vec.is_empty() => {
+                    let index = rng.gen_range(0..vec.len());
+                    seq[index] += 1;
+                    vec[index] += 1;
+                }
+                _ => {}
+            }
+        }
+
+        assert_eq!(seq.len(), vec.len());
+        assert!(seq.iter().eq(vec.iter()));
+        assert!(seq.iter().rev().eq(vec.iter().rev()));
+        for (i, val) in vec.iter().enumerate() {
+            assert_eq!(seq.get(i), Some(val));
+        }
+    }
+
+    #[test]
+    fn both_ends() {
+        let mut seq = TreapSeq::new();
+        seq.push_back(2);
+        seq.push_front(1);
+        seq.push_back(3);
+
+        assert_eq!(seq.first(), Some(&1));
+        assert_eq!(seq.last(), Some(&3));
+        assert_eq!(seq.pop_front(), Some(1));
+        assert_eq!(seq.pop_back(), Some(3));
+        assert_eq!(seq.pop_back(), Some(2));
+        assert_eq!(seq.pop_back(), None);
+        assert!(seq.is_empty());
+    }
+
+    #[test]
+    fn split_and_concat() {
+        let seq: TreapSeq<_> = (0..100).collect();
+
+        let (left, right) = seq.split_at(30);
+        assert!(left.iter().copied().eq(0..30));
+        assert!(right.iter().copied().eq(30..100));
+
+        let seq = right.concat(left);
+        assert!(seq.into_iter().eq((30..100).chain(0..30)));
+    }
+
+    #[test]
+    fn splice() {
+        let mut seq: TreapSeq<_> = (0..10).collect();
+
+        let removed = seq.splice(2..5, [20, 30]);
+        assert!(removed.iter().copied().eq(2..5));
+        assert!(seq.iter().copied().eq([0, 1, 20, 30, 5, 6, 7, 8, 9]));
+
+        let removed = seq.splice(..=1, []);
+        assert!(removed.iter().copied().eq([0, 1]));
+        assert!(seq.iter().copied().eq([20, 30, 5, 6, 7, 8, 9]));
+
+        seq.splice(7.., 0..2);
+        assert!(seq.iter().copied().eq([20, 30, 5, 6, 7, 8, 9, 0, 1]));
+    }
+
+    #[test]
+    fn reverse_and_rotate() {
+        let mut rng = StdRng::seed_from_u64(12);
+        let mut seq: TreapSeq<_> = (0..500).collect();
+        let mut vec: Vec<_> = (0..500).collect();
+
+        for _ in 0..500 {
+            let start = rng.gen_range(0..=vec.len());
+            let end = rng.gen_range(start..=vec.len());
+            let k = rng.gen_range(0..=end - start);
+
+            match rng.gen_range(0..5) {
+                0 => {
+                    seq.reverse(start..end);
+                    vec[start..end].reverse();
+                }
+                1 => {
+                    seq.rotate_left(start..end, k);
+                    vec[start..end].rotate_left(k);
+                }
+                2 => {
+                    seq.rotate_right(start..end, k);
+                    vec[start..end].rotate_right(k);
+                }
+                3 => {
+                    let val = seq.remove(start.min(vec.len() - 1));
+                    assert_eq!(val, vec.remove(start.min(vec.len() - 1)));
+                    seq.insert(end.min(vec.len()), val);
+                    vec.insert(end.min(vec.len()), val);
+                }
+                _ => {
+                    let index = start.min(vec.len() - 1);
+                    assert_eq!(seq[index], vec[index]);
+                    seq[index] += 1000;
+                    vec[index] += 1000;
+                }
+            }
+        }
+
+        assert!(seq.iter().eq(vec.iter()));
+        assert!(seq.iter().rev().eq(vec.iter().rev()));
+        assert_eq!(seq.first(), vec.first());
+        assert_eq!(seq.last(), vec.last());
+        for (i, val) in vec.iter().enumerate() {
+            assert_eq!(seq.get(i), Some(val));
+        }
+
+        seq.reverse(..);
+        assert!(seq.iter_mut().map(|v| *v).eq(vec.iter().rev().copied()));
+        assert!(seq.into_iter().eq(vec.into_iter().rev()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn rotate_past_range() {
+        let mut seq: TreapSeq<_> = (0..10).collect();
+        seq.rotate_left(2..4, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "range end index overflow")]
+    fn range_end_overflow() {
+        let mut seq: TreapSeq<_> = (0..10).collect();
+        seq.reverse(..=usize::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "range start index overflow")]
+    fn range_start_overflow() {
+        let mut seq: TreapSeq<_> = (0..10).collect();
+        seq.reverse((Bound::Excluded(usize::MAX), Bound::Unbounded));
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_out_of_bounds() {
+        let mut seq = TreapSeq::new();
+        seq.insert(1, ());
+    }
+}
@@ -0,0 +1,436 @@
+use std::{
+    borrow::Borrow,
+    fmt,
+    iter::FusedIterator,
+    ops::{BitAnd, BitOr, BitXor, RangeBounds, Sub},
+};
+
+use rand::RngCore;
+
+use crate::{
+    map::{self, TreapMap},
+    priority::{DefaultPriority, PrioritySource},
+};
+
+/// An ordered set, stored as a [`TreapMap`] with `()` values.
+pub struct TreapSet<K, P = DefaultPriority> {
+    map: TreapMap<K, (), P>,
+}
+
+impl<K> TreapSet<K> {
+    pub fn new() -> Self {
+        Self {
+            map: TreapMap::new(),
+        }
+    }
+
+    /// Creates a set whose priorities are drawn from a generator seeded
+    /// with `seed`, so that the same operations always build the same tree.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            map: TreapMap::with_seed(seed),
+        }
+    }
+}
+
+impl<K, R: RngCore> TreapSet<K, R> {
+    /// Creates a set whose priorities are drawn from `rng`.
+    pub fn with_rng(rng: R) -> Self {
+        Self {
+            map: TreapMap::with_rng(rng),
+        }
+    }
+}
+
+impl<K, P> TreapSet<K, P> {
+    pub fn with_priorities(priorities: P) -> Self {
+        Self {
+            map: TreapMap::with_priorities(priorities),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Adds a value to the set, returning whether it was newly inserted.
+    pub fn insert(&mut self, key: K) -> bool
+    where
+        K: Ord,
+        P: PrioritySource,
+    {
+        self.map.insert(key, ()).is_none()
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.map.get(key).is_some()
+    }
+
+    /// Removes a value from the set, returning whether it was present.
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.map.remove(key).is_some()
+    }
+
+    /// Removes and returns the value in the set equal to `key`, if any.
+    pub fn take<Q>(&mut self, key: &Q) -> Option<K>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.map.remove_entry(key).map(|(key, _)| key)
+    }
+
+    /// Iterates over the values of the set in ascending order.
+    pub fn iter(&self) -> Iter<'_, K> {
+        Iter {
+            inner: self.map.keys(),
+        }
+    }
+
+    /// Iterates over the values within `range` in ascending order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end, or if both
+    /// ends are equal and excluded.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        Range {
+            inner: self.map.range(range),
+        }
+    }
+
+    /// Returns whether every value in `self` is also in `other`.
+    pub fn is_subset(&self, other: &Self) -> bool
+    where
+        K: Ord,
+    {
+        self.len() <= other.len() && self.iter().all(|key| other.contains(key))
+    }
+
+    /// Returns whether every value in `other` is also in `self`.
+    pub fn is_superset(&self, other: &Self) -> bool
+    where
+        K: Ord,
+    {
+        other.is_subset(self)
+    }
+
+    /// Returns whether `self` and `other` have no values in common.
+    pub fn is_disjoint(&self, other: &Self) -> bool
+    where
+        K: Ord,
+    {
+        let (small, large) = if self.len() <= other.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+
+        !small.iter().any(|key| large.contains(key))
+    }
+}
+
+impl<K, P: Default> Default for TreapSet<K, P> {
+    fn default() -> Self {
+        Self::with_priorities(P::default())
+    }
+}
+
+impl<K: fmt::Debug, P> fmt::Debug for TreapSet<K, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Ord, P: PrioritySource + Default> FromIterator<K> for TreapSet<K, P> {
+    fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
+        let mut set = Self::default();
+        set.extend(iter);
+
+        set
+    }
+}
+
+impl<K: Ord, P: PrioritySource> Extend<K> for TreapSet<K, P> {
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        self.map.extend(iter.into_iter().map(|key| (key, ())));
+    }
+}
+
+/// The union of two sets, computed by splitting and joining their trees.
+impl<K: Ord, P> BitOr for TreapSet<K, P> {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self {
+            map: self.map.union(rhs.map, |_, _, _| ()),
+        }
+    }
+}
+
+/// The intersection of two sets, computed by splitting and joining their
+/// trees.
+impl<K: Ord, P> BitAnd for TreapSet<K, P> {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self {
+            map: self.map.intersection(rhs.map),
+        }
+    }
+}
+
+/// The values of the left set that are not in the right one, computed by
+/// splitting and joining their trees.
+impl<K: Ord, P> Sub for TreapSet<K, P> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            map: self.map.difference(rhs.map),
+        }
+    }
+}
+
+/// The values in exactly one of the two sets, computed by splitting and
+/// joining their trees.
+impl<K: Ord, P> BitXor for TreapSet<K, P> {
+    type Output = Self;
+
+    fn bitxor(self, rhs: Self) -> Self {
+        Self {
+            map: self.map.symmetric_difference(rhs.map),
+        }
+    }
+}
+
+impl<K: Ord + Clone, P: PrioritySource + Default> TreapSet<K, P> {
+    /// Copies the set into a new one with a fresh priority source, in linear
+    /// time since the keys come out sorted.
+    fn to_owned_set(&self) -> Self {
+        self.iter().cloned().collect()
+    }
+}
+
+/// The union of two borrowed sets, as a new set of cloned values.
+impl<K: Ord + Clone, P: PrioritySource + Default> BitOr<&TreapSet<K, P>> for &TreapSet<K, P> {
+    type Output = TreapSet<K, P>;
+
+    fn bitor(self, rhs: &TreapSet<K, P>) -> TreapSet<K, P> {
+        self.to_owned_set() | rhs.to_owned_set()
+    }
+}
+
+/// The intersection of two borrowed sets, as a new set of cloned values.
+impl<K: Ord + Clone, P: PrioritySource + Default> BitAnd<&TreapSet<K, P>> for &TreapSet<K, P> {
+    type Output = TreapSet<K, P>;
+
+    fn bitand(self, rhs: &TreapSet<K, P>) -> TreapSet<K, P> {
+        self.to_owned_set() & rhs.to_owned_set()
+    }
+}
+
+/// The values of the left borrowed set that are not in the right one, as a
+/// new set of cloned values.
+impl<K: Ord + Clone, P: PrioritySource + Default> Sub<&TreapSet<K, P>> for &TreapSet<K, P> {
+    type Output = TreapSet<K, P>;
+
+    fn sub(self, rhs: &TreapSet<K, P>) -> TreapSet<K, P> {
+        self.to_owned_set() - rhs.to_owned_set()
+    }
+}
+
+/// The values in exactly one of two borrowed sets, as a new set of cloned
+/// values.
+impl<K: Ord + Clone, P: PrioritySource + Default> BitXor<&TreapSet<K, P>> for &TreapSet<K, P> {
+    type Output = TreapSet<K, P>;
+
+    fn bitxor(self, rhs: &TreapSet<K, P>) -> TreapSet<K, P> {
+        self.to_owned_set() ^ rhs.to_owned_set()
+    }
+}
+
+impl<'a, K, P> IntoIterator for &'a TreapSet<K, P> {
+    type Item = &'a K;
+    type IntoIter = Iter<'a, K>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, P> IntoIterator for TreapSet<K, P> {
+    type Item = K;
+    type IntoIter = IntoIter<K>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            inner: self.map.into_keys(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Iter<'a, K> {
+    inner: map::Keys<'a, K, ()>,
+}
+
+impl<'a, K> Iterator for Iter<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K> DoubleEndedIterator for Iter<'_, K> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl<K> ExactSizeIterator for Iter<'_, K> {}
+impl<K> FusedIterator for Iter<'_, K> {}
+
+pub struct IntoIter<K> {
+    inner: map::IntoKeys<K, ()>,
+}
+
+impl<K> Iterator for IntoIter<K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K> DoubleEndedIterator for IntoIter<K> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl<K> ExactSizeIterator for IntoIter<K> {}
+impl<K> FusedIterator for IntoIter<K> {}
+
+#[derive(Clone)]
+pub struct Range<'a, K> {
+    inner: map::Range<'a, K, ()>,
+}
+
+impl<'a, K> Iterator for Range<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, _)| key)
+    }
+}
+
+impl<K> DoubleEndedIterator for Range<'_, K> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(key, _)| key)
+    }
+}
+
+impl<K> FusedIterator for Range<'_, K> {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    #[test]
+    fn insert_contains_remove() {
+        let mut set = TreapSet::new();
+
+        assert!(set.insert("b"));
+        assert!(set.insert("a"));
+        assert!(!set.insert("a"));
+        assert!(set.contains("a"));
+        assert_eq!(set.len(), 2);
+
+        assert!(set.remove("a"));
+        assert!(!set.remove("a"));
+        assert_eq!(set.take("b"), Some("b"));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn iter_and_range() {
+        let set: TreapSet<_> = (0..20).rev().collect();
+
+        assert!(set.iter().copied().eq(0..20));
+        assert!(set.range(5..8).copied().eq(5..8));
+        assert!(set.range(15..).rev().copied().eq((15..20).rev()));
+        assert!(set.into_iter().eq(0..20));
+    }
+
+    #[test]
+    fn operators() {
+        let a: BTreeSet<u32> = (0..200).map(|i| i * 3 % 250).collect();
+        let b: BTreeSet<u32> = (0..200).map(|i| i * 5 % 300).collect();
+        let treap = |set: &BTreeSet<u32>| set.iter().copied().collect::<TreapSet<_>>();
+
+        assert!((treap(&a) | treap(&b)).iter().eq((&a | &b).iter()));
+        assert!((treap(&a) & treap(&b)).iter().eq((&a & &b).iter()));
+        assert!((treap(&a) - treap(&b)).iter().eq((&a - &b).iter()));
+        assert!((treap(&a) ^ treap(&b)).iter().eq((&a ^ &b).iter()));
+    }
+
+    #[test]
+    fn operators_on_references() {
+        let a: BTreeSet<u32> = (0..200).map(|i| i * 3 % 250).collect();
+        let b: BTreeSet<u32> = (0..200).map(|i| i * 5 % 300).collect();
+        let (x, y): (TreapSet<_>, TreapSet<_>) =
+            (a.iter().copied().collect(), b.iter().copied().collect());
+
+        assert!((&x | &y).iter().eq((&a | &b).iter()));
+        assert!((&x & &y).iter().eq((&a & &b).iter()));
+        assert!((&x - &y).iter().eq((&a - &b).iter()));
+        assert!((&x ^ &y).iter().eq((&a ^ &b).iter()));
+
+        // the operands are left as they were
+        assert!(x.iter().eq(a.iter()));
+        assert!(y.iter().eq(b.iter()));
+    }
+
+    #[test]
+    fn subsets() {
+        let small: TreapSet<_> = [2, 4].into_iter().collect();
+        let large: TreapSet<_> = (0..5).collect();
+        let other: TreapSet<_> = [7, 9].into_iter().collect();
+
+        assert!(small.is_subset(&large));
+        assert!(!large.is_subset(&small));
+        assert!(large.is_superset(&small));
+        assert!(small.is_disjoint(&other));
+        assert!(!small.is_disjoint(&large));
+        assert!(TreapSet::new().is_subset(&small));
+    }
+}